@@ -1,8 +1,8 @@
 #![no_std]
 
 use soroban_sdk::{
-    contract, contractimpl, contracttype, contractclient, Address, Env, String, Vec,
-    symbol_short, log, Error as SorobanError,
+    contract, contracterror, contractimpl, contracttype, contractclient, Address, BytesN, Env, IntoVal, String,
+    TryFromVal, Val, Vec, symbol_short, log, token::TokenClient,
 };
 
 // ============ DATA TYPES ============
@@ -11,8 +11,35 @@ use soroban_sdk::{
 #[derive(Clone)]
 pub enum DataKey {
     Plan(String),           // Plan identified by plan_id
+    Receipt(String, u32),   // Payment receipt per (plan_id, installment)
     UserPlans(Address),     // List of plans for a user
+    Dispute(String),        // Open dispute of a plan
+    Escrow(String),         // Delivery escrow of a plan
     PlanCounter,            // Counter to generate unique IDs
+    OrderRef(Address, String), // (merchant, order_ref) -> plan_id
+    Invoice(String),        // Merchant payment request identified by invoice_id
+    InvoiceCounter,         // Counter to generate unique invoice IDs
+    InvoiceOrderRef(Address, String), // (merchant, order_ref) -> invoice_id reserving it
+    MerchantTerms(Address), // Terms a merchant pre-authorized for new plans
+    CreditMerchant(Address), // Merchants allowed to receive credit line draws
+    CreditLine(Address),    // Revolving credit line of a user
+    Subscription(String),   // Recurring merchant payment identified by subscription_id
+    SubscriptionCounter,    // Counter to generate unique subscription IDs
+    Admin,                  // Bridge administrator
+    Config,                 // Bridge configuration
+    PaymentToken,           // Token pulled from user wallets
+    BufferContract,         // Buffer Contract holding the collateral
+    BountyPool,             // Protocol fees available for keeper bounties
+    SchemaVersion,          // Storage layout version, bumped by migrate()
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct BridgeConfig {
+    pub max_forbearance_count: u32,  // Forbearance windows allowed per plan
+    pub max_forbearance_days: u32,   // Total forbearance days allowed per plan
+    pub keeper_bounty_bps: u32,      // Keeper bounty in bps of the installment
+    pub keeper_bounty_flat: i128,    // Flat keeper bounty per collection
 }
 
 #[contracttype]
@@ -21,6 +48,9 @@ pub enum PlanStatus {
     Active,      // Active plan with pending installments
     Completed,   // Plan completed - all installments paid
     Defaulted,   // Plan in default - some installment failed
+    Restructured, // Active plan whose remaining schedule was renegotiated
+    InDispute,   // Collection frozen while a dispute is open
+    Cancelled,   // Plan cancelled by dispute resolution
 }
 
 #[contracttype]
@@ -29,35 +59,59 @@ pub enum InstallmentStatus {
     Pending,  // Installment pending payment
     Paid,     // Installment paid successfully
     Failed,   // Installment failed due to lack of funds
+    Waived,   // Installment forgiven by the merchant (dispute refund)
+    Held,     // Collected into escrow, waiting for delivery
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisputeOutcome {
+    Continue,            // Dispute rejected, plan resumes as before
+    PartialRefund(i128), // Merchant forgives part of the unpaid balance
+    FullCancel,          // Plan cancelled, all collateral released
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct Dispute {
+    pub reason: u32,                 // Reason code supplied by the buyer
+    pub opened_at: u64,              // Timestamp the dispute was opened
+    pub prior_status: PlanStatus,    // Status to restore when resolved
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Payee {
+    pub payee: Address,              // Address receiving part of each payment
+    pub bps: u32,                    // Share of each payment in basis points
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct Escrow {
+    pub delivery_deadline: u64,      // Held installments are released after this date
+    pub delivered_at: Option<u64>,   // Release timestamp (None = goods not delivered yet)
+    pub held_amount: i128,           // Payment tokens the bridge holds for this plan
 }
 
-// ============================================================
-// TECHNICAL NOTE: PaymentSource implementation
-// ============================================================
 #[contracttype]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct PaymentSource(pub u32);
+pub enum PaymentSource {
+    Available,   // Available buffer shares
+    Protected,   // Protected buffer shares (collateral)
+    Wallet,      // User wallet tokens via allowance to the bridge
+}
 
-impl PaymentSource {
-    pub fn available() -> Self {
-        Self(0)
-    }
-    
-    pub fn protected() -> Self {
-        Self(1)
-    }
-    
-    pub fn is_available(&self) -> bool {
-        self.0 == 0
-    }
-    
-    pub fn is_protected(&self) -> bool {
-        self.0 == 1
-    }
-    
-    pub fn to_u32(&self) -> u32 {
-        self.0
-    }
+#[contracttype]
+#[derive(Clone)]
+pub struct PaymentReceipt {
+    pub installment_number: u32,     // Installment this receipt settles
+    pub source: PaymentSource,       // Where the payment came from
+    pub shares_burned: i128,         // Buffer shares burned (0 for wallet)
+    pub amounts_received: Vec<i128>, // Amounts received per asset by the merchant
+    pub merchant_amount: i128,       // Total settled to the merchant
+    pub fee_amount: i128,            // Keeper bounty paid out of the pool
+    pub paid_at: u64,                // Transaction timestamp
 }
 
 #[contracttype]
@@ -67,14 +121,137 @@ pub struct Installment {
     pub amount: i128,
     pub due_date: u64,
     pub paid_at: Option<u64>,
-    pub payment_source: Option<PaymentSource>,
     pub status: InstallmentStatus,
 }
 
+#[contracttype]
+#[derive(Clone)]
+pub struct ScheduleRevision {
+    pub installments: Vec<Installment>, // Schedule as it was before the change
+    pub replaced_at: u64,               // Timestamp of the restructuring
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanMetadata {
+    pub order_ref: Option<String>,         // Merchant order number, unique per merchant
+    pub invoice_hash: Option<BytesN<32>>,  // Hash of the POS invoice
+    pub category_code: Option<u32>,        // Item category code
+    pub currency_code: Option<String>,     // Currency of the purchase (e.g. USD)
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct PlanOptions {
+    pub metadata: PlanMetadata,            // Order information (all fields optional)
+    pub guarantor: Option<Address>,        // Co-signer backing the plan with their buffer
+    pub delivery_deadline: Option<u64>,    // Hold installments in escrow until this date
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuthPath {
+    CoSigned,      // Merchant signed the plan creation
+    PreAuthorized, // Plan fits the merchant's pre-authorized terms
+    Invoice,       // Plan created from a merchant invoice
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct MerchantTerms {
+    pub max_amount: i128,            // Maximum amount per plan
+    pub min_installments: u32,       // Minimum number of installments
+    pub max_installments: u32,       // Maximum number of installments
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvoiceStatus {
+    Open,        // Waiting for a customer to accept it
+    Accepted,    // Accepted - a plan was created from it
+    Cancelled,   // Cancelled by the merchant
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct InstallmentTemplate {
+    pub installments_count: u32,     // Number of installments (1-12)
+    pub interval_secs: u64,          // Time between acceptance and each due date
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct Invoice {
+    pub invoice_id: String,          // Unique invoice ID (e.g. INV-000123)
+    pub merchant: Address,           // Merchant who published the invoice
+    pub amount: i128,                // Amount to finance in tokens
+    pub templates: Vec<InstallmentTemplate>, // Installment options offered
+    pub metadata: PlanMetadata,      // Order information copied to the plan
+    pub expires_at: u64,             // Invoice cannot be accepted after this
+    pub status: InvoiceStatus,       // Current invoice status
+    pub plan_id: Option<String>,     // Plan created on acceptance
+    pub created_at: u64,             // Creation timestamp
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct CreditDraw {
+    pub merchant: Address,           // Merchant paid by this draw
+    pub outstanding: i128,           // Amount still owed to the merchant
+    pub locked_shares: i128,         // Collateral still backing this draw
+    pub lien_id: u64,                // Buffer lien holding the collateral
+    pub lien_expires_at: u64,        // Lien expiry, pushed out by each statement
+    pub drawn_at: u64,               // Draw timestamp
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct CreditStatement {
+    pub balance: i128,               // Outstanding balance when the statement closed
+    pub minimum_due: i128,           // Minimum payment still to collect
+    pub due_date: u64,               // Minimum payment collectable from this date
+    pub closed_at: u64,              // Statement closing timestamp (0 = none yet)
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct CreditLine {
+    pub user: Address,               // Owner of the credit line
+    pub outstanding: i128,           // Total amount drawn and not yet repaid
+    pub locked_shares: i128,         // Buffer shares locked as collateral
+    pub draws: Vec<CreditDraw>,      // Unpaid draws, oldest first
+    pub statement: CreditStatement,  // Last monthly statement
+    pub next_statement_at: u64,      // When the next statement can be closed
+    pub opened_at: u64,              // Creation timestamp
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubscriptionStatus {
+    Active,      // Charges are collected every period
+    Cancelled,   // Cancelled by the user, or cap reached
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct Subscription {
+    pub subscription_id: String,     // Unique ID (e.g. SUB-000123)
+    pub user: Address,               // Subscriber paying the charges
+    pub merchant: Address,           // Merchant receiving the charges
+    pub amount: i128,                // Amount charged per period
+    pub period_secs: u64,            // Time between charges
+    pub cap: Option<i128>,           // Maximum total to charge (None = open-ended)
+    pub charged_total: i128,         // Total charged so far
+    pub charges_count: u32,          // Number of charges collected
+    pub next_charge_at: u64,         // Next charge collectable from this date
+    pub status: SubscriptionStatus,  // Current subscription status
+    pub created_at: u64,             // Creation timestamp
+}
+
 #[contracttype]
 #[derive(Clone)]
 pub struct BridgePlan {
-    pub plan_id: String,             // Unique plan ID
+    pub plan_id: String,             // Unique plan ID (e.g. PLN-000123)
     pub user: Address,               // User who created the plan
     pub merchant: Address,           // Merchant who receives payments
     pub total_amount: i128,          // Total plan amount in tokens
@@ -84,6 +261,19 @@ pub struct BridgePlan {
     pub protected_shares: i128,      // Shares currently protected (decreasing)
     pub status: PlanStatus,          // Current plan status
     pub created_at: u64,             // Creation timestamp
+    pub schedule_history: Vec<ScheduleRevision>, // Prior schedules (restructurings)
+    pub forbearance_until: u64,      // End of the current forbearance window (0 = none)
+    pub forbearance_count: u32,      // Forbearance windows granted so far
+    pub forbearance_days: u32,       // Forbearance days granted so far
+    pub payment_preference: Vec<PaymentSource>, // Order in which sources are tried
+    pub metadata: PlanMetadata,      // Order information from the POS
+    pub auth_path: AuthPath,         // How the merchant authorized the plan
+    pub payees: Vec<Payee>,          // Split of each payment (empty = merchant only)
+    pub guarantor: Option<Address>,  // Co-signer backing part of the collateral
+    pub guarantor_shares: i128,      // Shares locked in the guarantor's buffer
+    pub lien_id: u64,                // Buffer lien on the user's shares (0 = none)
+    pub guarantor_lien_id: u64,      // Buffer lien on the guarantor's shares (0 = none)
+    pub lien_expires_at: u64,        // After this the user can reclaim locked shares
 }
 
 // ============ BUFFER CONTRACT INTERFACE ============
@@ -101,6 +291,7 @@ pub struct BufferBalance {
 #[contracttype]
 #[derive(Clone)]
 pub struct LockResult {
+    pub lien_id: u64,              // Lien created or reduced by the operation
     pub shares_locked: i128,       // Amount of shares that were locked
     pub new_available: i128,       // New balance of available shares
     pub new_protected: i128,       // New balance of protected shares
@@ -115,23 +306,43 @@ pub struct WithdrawResult {
     pub from_protected: bool,           // Whether debited from protected
 }
 
+#[contracttype]
+#[derive(Clone)]
+pub struct Lien {
+    pub id: u64,                   // Lien identifier in the buffer
+    pub user: Address,             // Owner of the locked shares
+    pub owner: Address,            // Consumer holding the lien
+    pub shares: i128,              // Shares still locked
+    pub expires_at: u64,           // After this the user can reclaim the shares
+    pub created_at: u64,           // Lock timestamp
+}
+
 // Client to call Buffer Contract functions
 #[contractclient(name = "BufferContractClient")]
 pub trait BufferContract {
     // Get user balance
     fn get_balance(env: Env, user: Address) -> BufferBalance;
     
-    // Lock shares as collateral
-    fn lock_shares(env: Env, user: Address, shares: i128) -> LockResult;
+    // Lock shares as collateral under a new lien held by the consumer
+    fn lock_shares(env: Env, consumer: Address, user: Address, shares: i128, expires_at: Option<u64>) -> LockResult;
+    
+    // Unlock shares of a lien (release collateral)
+    fn unlock_shares(env: Env, user: Address, lien_id: u64, shares: i128) -> LockResult;
+    
+    // Lien by ID, None once it was released or reclaimed
+    fn find_lien(env: Env, lien_id: u64) -> Option<Lien>;
     
-    // Unlock shares (release collateral)
-    fn unlock_shares(env: Env, user: Address, shares: i128) -> LockResult;
+    // Push back the expiry of a lien
+    fn extend_lien(env: Env, user: Address, lien_id: u64, expires_at: u64);
     
-    // Debit from available shares
-    fn debit_available(env: Env, user: Address, shares: i128, to: Address) -> WithdrawResult;
+    // Available shares the user allows the consumer to debit
+    fn allowance(env: Env, user: Address, consumer: Address) -> i128;
     
-    // Debit from protected shares (fallback)
-    fn debit_protected(env: Env, user: Address, shares: i128, to: Address) -> WithdrawResult;
+    // Debit from available shares on behalf of the consumer (spends the allowance)
+    fn debit_available(env: Env, consumer: Address, user: Address, shares: i128, to: Address) -> WithdrawResult;
+    
+    // Debit from the protected shares of a lien (fallback)
+    fn debit_protected(env: Env, user: Address, lien_id: u64, shares: i128, to: Address) -> WithdrawResult;
     
     // Get values in tokens (available, protected, total)
     fn get_values(env: Env, user: Address) -> (i128, i128, i128);
@@ -148,12 +359,90 @@ const MAX_LTV_BPS: i128 = 8000;
 
 /// Liquidation threshold in basis points (for future alerts)
 /// 8500 = 85% - Point where risk should be alerted
+#[allow(dead_code)]
 const LIQUIDATION_THRESHOLD_BPS: i128 = 8500;
 
+// ============ CREDIT LINE CONSTANTS ============
+
+/// Length of a credit line statement cycle (30 days)
+const STATEMENT_PERIOD_SECS: u64 = 30 * 86400;
+
+/// Time between statement closing and minimum payment collection (15 days)
+const STATEMENT_GRACE_SECS: u64 = 15 * 86400;
+
+/// Minimum payment in basis points of the statement balance
+/// 1000 = 10% - The rest revolves to the next statement
+const MIN_PAYMENT_BPS: i128 = 1000;
+
+// ============ FORBEARANCE CONSTANTS ============
+
+const SECONDS_PER_DAY: u64 = 86400;
+const DEFAULT_MAX_FORBEARANCE_COUNT: u32 = 2;
+const DEFAULT_MAX_FORBEARANCE_DAYS: u32 = 90;
+
+/// Highest forbearance caps the admin can configure
+const MAX_FORBEARANCE_COUNT: u32 = 12;
+const MAX_FORBEARANCE_DAYS: u32 = 365;
+
+/// Collateral liens outlive the last due date by this grace period
+const LIEN_GRACE_SECS: u64 = 90 * SECONDS_PER_DAY;
+
+/// Longest lien the Buffer Contract accepts (its MAX_LOCK_DURATION_SECS)
+const MAX_LIEN_DURATION_SECS: u64 = 730 * SECONDS_PER_DAY;
+
+// ============ KEEPER CONSTANTS ============
+
+/// Highest keeper bounty the admin can configure (1% of the installment)
+const MAX_KEEPER_BOUNTY_BPS: u32 = 100;
+
+// ============ UPGRADE CONSTANTS ============
+
+/// Storage layout written by this version of the contract.
+/// Bump it together with a new step in `migrate` whenever a stored type changes.
+const SCHEMA_VERSION: u32 = 1;
+
+// ============ STORAGE TTL CONSTANTS ============
+
+/// Ledgers closed per day (~5 seconds per ledger)
+const DAY_IN_LEDGERS: u32 = 17280;
+
+/// Plans, receipts and credit lines live 120 days past their last use
+const PERSISTENT_BUMP_AMOUNT: u32 = 120 * DAY_IN_LEDGERS;
+
+/// Entries are bumped again once less than 90 days are left
+const PERSISTENT_LIFETIME_THRESHOLD: u32 = PERSISTENT_BUMP_AMOUNT - 30 * DAY_IN_LEDGERS;
+
+/// Admin, config and counters: same window as the plans they serve
+const INSTANCE_BUMP_AMOUNT: u32 = PERSISTENT_BUMP_AMOUNT;
+
+/// Instance storage is bumped on every call past this threshold
+const INSTANCE_LIFETIME_THRESHOLD: u32 = PERSISTENT_LIFETIME_THRESHOLD;
+
+// ============ IDENTIFIER CONSTANTS ============
+
+/// Printable prefix of plan IDs (PLN-000123)
+const PLAN_ID_PREFIX: &[u8; 4] = b"PLN-";
+const PLAN_ID_MIN_DIGITS: usize = 6;
+
+/// Printable prefix of invoice IDs (INV-000123)
+const INVOICE_ID_PREFIX: &[u8; 4] = b"INV-";
+
+/// Printable prefix of subscription IDs (SUB-000123)
+const SUBSCRIPTION_ID_PREFIX: &[u8; 4] = b"SUB-";
+
+/// Maximum length in bytes of a merchant order reference
+const MAX_ORDER_REF_LEN: u32 = 64;
+
+/// Maximum length in bytes of a currency code (ISO 4217 or Stellar asset code)
+const MAX_CURRENCY_CODE_LEN: u32 = 12;
+
+/// Maximum number of payees splitting a plan's payments
+const MAX_PAYEES: u32 = 8;
+
 // ============ ERRORS ============
 
-#[contracttype]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[contracterror]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
 #[repr(u32)]
 pub enum ContractError {
     InvalidAmount = 1,           // Invalid or negative amount
@@ -171,54 +460,353 @@ pub enum ContractError {
     BufferContractError = 13,    // Error calling Buffer Contract
     InvalidShares = 14,          // Invalid shares calculation
     ExceedsMaxLTV = 15,          // Plan exceeds maximum Loan-to-Value ratio
+    PlanNotActive = 16,          // Plan is completed or defaulted
+    AmountsMismatch = 17,        // New amounts do not add up to the unpaid balance
+    PlanInDispute = 18,          // Plan is frozen by an open dispute
+    NotInDispute = 19,           // Plan has no open dispute
+    Unauthorized = 20,           // Caller is not allowed to perform the action
+    InForbearance = 21,          // Plan is inside a forbearance window
+    ForbearanceLimitExceeded = 22, // Forbearance count or days cap reached
+    InvalidPaymentSource = 23,   // Duplicated or missing payment source
+    OrderRefTaken = 24,          // Order reference already used by the merchant
+    InvalidOrderRef = 25,        // Order reference empty or too long
+    InvalidMetadata = 26,        // Metadata field empty or too long
+    InvoiceNotFound = 27,        // Invoice not found in storage
+    InvoiceNotOpen = 28,         // Invoice already accepted or cancelled
+    InvoiceExpired = 29,         // Invoice expiry has passed
+    InvalidTemplate = 30,        // Installment template invalid or not offered
+    InvalidTerms = 31,           // Merchant terms out of range
+    MerchantNotRegistered = 32,  // Merchant cannot receive credit line draws
+    CreditLineNotFound = 33,     // User has no credit line
+    CreditLineExists = 34,       // User already has a credit line
+    ExceedsCreditLimit = 35,     // Draw exceeds the available credit
+    StatementNotDue = 36,        // Statement cycle or minimum payment not due yet
+    SubscriptionNotFound = 37,   // Subscription not found in storage
+    SubscriptionNotActive = 38,  // Subscription cancelled
+    SubscriptionCapReached = 39, // Next charge would exceed the subscription cap
+    NotEscrowPlan = 40,          // Plan has no delivery escrow
+    InvalidPayees = 41,          // Too many payees or shares not adding up to 100%
+    InvalidGuarantor = 42,       // Guarantor is the plan's user
+    InvalidBounty = 43,          // Keeper bounty above the cap or negative
+    NoPaymentToken = 44,         // Payment token not configured
+    AlreadyMigrated = 45,        // Storage already at the current schema version
+    InvalidForbearanceCap = 46,  // Forbearance caps zero or above the maximum
+    NoBufferContract = 47,       // Buffer Contract not configured
+    Overflow = 48,               // Arithmetic overflow
 }
 
-// Conversion of our error to SorobanError
-impl From<ContractError> for SorobanError {
-    fn from(e: ContractError) -> Self {
-        SorobanError::from_contract_error(e as u32)
+// ============ MAIN CONTRACT ============
+
+#[contract]
+pub struct BridgeContract;
+
+#[contractimpl]
+impl BridgeContract {
+
+    pub fn __constructor(env: Env, admin: Address) {
+        Self::extend_instance(&env);
+        admin.require_auth();
+
+        let storage = env.storage().instance();
+        storage.set(&DataKey::Admin, &admin);
+        storage.set(&DataKey::Config, &BridgeConfig {
+            max_forbearance_count: DEFAULT_MAX_FORBEARANCE_COUNT,
+            max_forbearance_days: DEFAULT_MAX_FORBEARANCE_DAYS,
+            keeper_bounty_bps: 0,
+            keeper_bounty_flat: 0,
+        });
+        storage.set(&DataKey::SchemaVersion, &SCHEMA_VERSION);
     }
-}
 
-impl From<&ContractError> for SorobanError {
-    fn from(e: &ContractError) -> Self {
-        SorobanError::from_contract_error(*e as u32)
+    /// Replace the contract code, keeping its storage (admin only)
+    ///
+    /// Call `migrate` right after so storage matches the new code.
+    pub fn upgrade(env: Env, new_wasm_hash: BytesN<32>) {
+        Self::extend_instance(&env);
+        let admin = Self::get_admin(&env);
+        admin.require_auth();
+
+        env.deployer().update_current_contract_wasm(new_wasm_hash.clone());
+
+        env.events().publish((symbol_short!("upgraded"), new_wasm_hash), ());
     }
-}
 
-// Conversion of SorobanError to our error (catch-all)
-impl From<SorobanError> for ContractError {
-    fn from(_: SorobanError) -> Self {
-        ContractError::BufferContractError
+    /// Bring storage written by an older version up to the current schema (admin only)
+    ///
+    /// Schema 1 is the first versioned layout, so there is nothing to convert
+    /// yet: every deployment with `upgrade` stamped it in the constructor, and
+    /// builds from before versioning cannot be upgraded in place (redeploy them).
+    pub fn migrate(env: Env) -> Result<u32, ContractError> {
+        Self::extend_instance(&env);
+        let admin = Self::get_admin(&env);
+        admin.require_auth();
+
+        let from = Self::get_schema_version(env.clone());
+        if from >= SCHEMA_VERSION {
+            log!(&env, "Error: Storage already at schema version {}", from);
+            return Err(ContractError::AlreadyMigrated);
+        }
+
+        // Later layouts add one conversion step per version here, e.g.
+        // if from < 2 { Self::migrate_to_v2(&env); }
+
+        env.storage().instance().set(&DataKey::SchemaVersion, &SCHEMA_VERSION);
+
+        env.events().publish((symbol_short!("migrated"), from, SCHEMA_VERSION), ());
+
+        Ok(SCHEMA_VERSION)
     }
-}
 
-// ============ MAIN CONTRACT ============
+    /// Storage layout version (0 = written before versioning)
+    pub fn get_schema_version(env: Env) -> u32 {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::SchemaVersion).unwrap_or(0)
+    }
 
-#[contract]
-pub struct BridgeContract;
+    /// Update bridge configuration (admin only)
+    pub fn update_config(env: Env, config: BridgeConfig) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
+        let admin = Self::get_admin(&env);
+        admin.require_auth();
 
-#[contractimpl]
-impl BridgeContract {
+        if config.keeper_bounty_bps > MAX_KEEPER_BOUNTY_BPS || config.keeper_bounty_flat < 0 {
+            log!(&env, "Error: Invalid keeper bounty {} bps + {}",
+                config.keeper_bounty_bps, config.keeper_bounty_flat);
+            return Err(ContractError::InvalidBounty);
+        }
+
+        if config.max_forbearance_count == 0 || config.max_forbearance_count > MAX_FORBEARANCE_COUNT
+            || config.max_forbearance_days == 0 || config.max_forbearance_days > MAX_FORBEARANCE_DAYS {
+            log!(&env, "Error: Invalid forbearance caps {} windows, {} days",
+                config.max_forbearance_count, config.max_forbearance_days);
+            return Err(ContractError::InvalidForbearanceCap);
+        }
+
+        env.storage().instance().set(&DataKey::Config, &config);
+
+        env.events().publish((
+            symbol_short!("cfg_upd"),
+            config.max_forbearance_count,
+            config.max_forbearance_days,
+            config.keeper_bounty_bps,
+            config.keeper_bounty_flat,
+        ), ());
+
+        Ok(())
+    }
+
+    /// Set the token pulled from user wallets (admin only)
+    pub fn set_payment_token(env: Env, token: Address) {
+        Self::extend_instance(&env);
+        let admin = Self::get_admin(&env);
+        admin.require_auth();
+
+        env.storage().instance().set(&DataKey::PaymentToken, &token);
+
+        env.events().publish((symbol_short!("token_set"), token), ());
+    }
+
+    /// Set the Buffer Contract (admin only)
+    ///
+    /// Entrypoints still take the buffer address, but only this one is
+    /// accepted, so a keeper cannot point a collection at a fake buffer.
+    pub fn set_buffer_contract(env: Env, buffer_contract: Address) {
+        Self::extend_instance(&env);
+        let admin = Self::get_admin(&env);
+        admin.require_auth();
+
+        env.storage().instance().set(&DataKey::BufferContract, &buffer_contract);
+
+        env.events().publish((symbol_short!("buf_set"), buffer_contract), ());
+    }
+
+    /// Get the configured Buffer Contract
+    pub fn get_buffer_contract(env: Env) -> Option<Address> {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::BufferContract)
+    }
+
+    /// Add protocol fees to the keeper bounty pool
+    ///
+    /// Transfers payment tokens from `from` to the bridge. Bounties are
+    /// paid out of this pool only.
+    pub fn fund_bounty_pool(env: Env, from: Address, amount: i128) -> Result<i128, ContractError> {
+        Self::extend_instance(&env);
+        from.require_auth();
+
+        if amount <= 0 {
+            log!(&env, "Error: Invalid amount {}", amount);
+            return Err(ContractError::InvalidAmount);
+        }
+
+        let token = Self::get_payment_token(&env)?;
+
+        let pool = Self::get_bounty_pool(env.clone()).checked_add(amount)
+            .ok_or(ContractError::Overflow)?;
+
+        TokenClient::new(&env, &token).transfer(&from, &env.current_contract_address(), &amount);
+        env.storage().instance().set(&DataKey::BountyPool, &pool);
+
+        env.events().publish((symbol_short!("pool_fund"), from, amount), ());
+
+        Ok(pool)
+    }
+
+    /// Protocol fees left for keeper bounties
+    pub fn get_bounty_pool(env: Env) -> i128 {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::BountyPool).unwrap_or(0)
+    }
+
+    /// Get bridge configuration
+    pub fn get_config(env: Env) -> BridgeConfig {
+        Self::extend_instance(&env);
+        env.storage()
+            .instance()
+            .get(&DataKey::Config)
+            .unwrap_or(BridgeConfig {
+                max_forbearance_count: DEFAULT_MAX_FORBEARANCE_COUNT,
+                max_forbearance_days: DEFAULT_MAX_FORBEARANCE_DAYS,
+                keeper_bounty_bps: 0,
+                keeper_bounty_flat: 0,
+            })
+    }
+    
+    /// Pre-authorize plan terms for a merchant
+    ///
+    /// Plans within these terms can be created without the merchant
+    /// co-signing each one.
+    pub fn set_merchant_terms(
+        env: Env,
+        merchant: Address,
+        terms: MerchantTerms,
+    ) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
+        merchant.require_auth();
+
+        if terms.max_amount <= 0
+            || terms.min_installments == 0
+            || terms.min_installments > terms.max_installments
+            || terms.max_installments > 12 {
+            log!(&env, "Error: Invalid merchant terms");
+            return Err(ContractError::InvalidTerms);
+        }
+
+        Self::write_persistent(&env, &DataKey::MerchantTerms(merchant.clone()), &terms);
+
+        env.events().publish((
+            symbol_short!("terms_set"),
+            merchant,
+            terms.max_amount,
+            terms.min_installments,
+            terms.max_installments,
+        ), ());
+
+        Ok(())
+    }
+
+    /// Revoke a merchant's pre-authorized terms
+    pub fn revoke_merchant_terms(env: Env, merchant: Address) {
+        Self::extend_instance(&env);
+        merchant.require_auth();
+
+        env.storage()
+            .persistent()
+            .remove(&DataKey::MerchantTerms(merchant.clone()));
+
+        env.events().publish((symbol_short!("terms_rev"), merchant), ());
+    }
+
+    /// Get a merchant's pre-authorized terms
+    pub fn get_merchant_terms(env: Env, merchant: Address) -> Option<MerchantTerms> {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::MerchantTerms(merchant))
+    }
     
     /// Create an installment plan
     /// 
     /// Creates a new installment financing plan, locking Buffer shares
     /// as collateral. Validates that the user has sufficient collateral
-    /// and locks the necessary shares.
+    /// and locks the necessary shares. The merchant must either co-sign
+    /// or have pre-authorized terms that cover the plan.
+    ///
+    /// An optional guarantor (who must also sign) backs the plan with their
+    /// own Buffer: the LTV check combines both balances and the guarantor
+    /// locks only what the user's available balance cannot cover.
+    ///
+    /// With a `delivery_deadline`, collected installments are held by the
+    /// bridge until the buyer confirms delivery or the deadline passes (see
+    /// `release_escrow`). Installments collected after the release are paid
+    /// to the merchant directly.
     pub fn create_plan(
         env: Env,
         user: Address,               // User who creates the plan
         merchant: Address,           // Merchant who will receive payments
         total_amount: i128,          // Total amount to finance
-        installments_count: u32,     // Number of installments (1-12)
-        due_dates: Vec<u64>,         // Due dates of each installment
-        buffer_contract: Address,    // Address of Buffer Contract
+        due_dates: Vec<u64>,         // Due dates of each installment (1-12)
+        options: PlanOptions,        // Metadata, guarantor and escrow options
     ) -> Result<String, ContractError> {
+        Self::extend_instance(&env);
         
         // Verify that user signed the transaction
         user.require_auth();
         
+        if let Some(guarantor) = &options.guarantor {
+            if *guarantor == user {
+                log!(&env, "Error: User cannot guarantee their own plan");
+                return Err(ContractError::InvalidGuarantor);
+            }
+            guarantor.require_auth();
+        }
+        
+        let auth_path = Self::require_merchant_auth(&env, &merchant, total_amount, due_dates.len());
+        
+        Self::create_plan_internal(
+            env,
+            user,
+            merchant,
+            total_amount,
+            due_dates,
+            options,
+            auth_path,
+        )
+    }
+    
+    /// Pre-authorized terms avoid a merchant signature, otherwise co-sign
+    fn require_merchant_auth(
+        env: &Env,
+        merchant: &Address,
+        total_amount: i128,
+        installments_count: u32,
+    ) -> AuthPath {
+        let pre_authorized = match Self::get_merchant_terms(env.clone(), merchant.clone()) {
+            Some(terms) => total_amount <= terms.max_amount
+                && installments_count >= terms.min_installments
+                && installments_count <= terms.max_installments,
+            None => false,
+        };
+        
+        if pre_authorized {
+            AuthPath::PreAuthorized
+        } else {
+            merchant.require_auth();
+            AuthPath::CoSigned
+        }
+    }
+    
+    fn create_plan_internal(
+        env: Env,
+        user: Address,
+        merchant: Address,
+        total_amount: i128,
+        due_dates: Vec<u64>,
+        options: PlanOptions,
+        auth_path: AuthPath,
+    ) -> Result<String, ContractError> {
+        
+        let PlanOptions { metadata, guarantor, delivery_deadline } = options;
+        let installments_count = due_dates.len();
+        
         // ===== BASIC VALIDATIONS =====
         
         if total_amount <= 0 {
@@ -231,13 +819,9 @@ impl BridgeContract {
             return Err(ContractError::InvalidInstallments);
         }
         
-        if due_dates.len() != installments_count {
-            log!(&env, "Error: Number of dates {} does not match installments {}", 
-                due_dates.len(), installments_count);
-            return Err(ContractError::DatesMismatch);
-        }
+        Self::validate_metadata(&env, &merchant, &metadata)?;
         
-        // Validate that all dates are in the future
+        // Validate that all dates (and the delivery deadline) are in the future
         let current_time = env.ledger().timestamp();
         for i in 0..due_dates.len() {
             let date = due_dates.get(i).unwrap();
@@ -247,17 +831,36 @@ impl BridgeContract {
             }
         }
         
+        if let Some(deadline) = delivery_deadline {
+            if deadline <= current_time {
+                log!(&env, "Error: Delivery deadline in the past {}", deadline);
+                return Err(ContractError::InvalidDueDate);
+            }
+            
+            // Held payments are kept and paid out in the payment token
+            Self::get_payment_token(&env)?;
+        }
+        
         // ===== QUERY BUFFER AND VALIDATE COLLATERALIZATION =====
         
-        let buffer_client = BufferContractClient::new(&env, &buffer_contract);
+        let buffer_client = Self::buffer_client(&env)?;
         
         // Get values in tokens for validation
         let (available_value, _, total_value) = buffer_client.get_values(&user);
         
+        // The guarantor's buffer adds to the collateral base
+        let (guarantor_available, guarantor_total) = match &guarantor {
+            Some(guarantor) => {
+                let (available, _, total) = buffer_client.get_values(guarantor);
+                (available, total)
+            }
+            None => (0, 0),
+        };
+        
         // ===== LTV VALIDATION: Calculate maximum allowed amount =====
         // MAX_LTV_BPS = 8000 means 80%
         // max_bridge_amount = total_value * 80 / 100 = total_value * 0.8
-        let max_bridge_amount = (total_value * MAX_LTV_BPS) / 10000;
+        let max_bridge_amount = ((total_value + guarantor_total) * MAX_LTV_BPS) / 10000;
         
         log!(&env, "Total Buffer: {}, Max allowed (LTV 80%): {}, Requested: {}", 
             total_value, max_bridge_amount, total_amount);
@@ -269,23 +872,59 @@ impl BridgeContract {
         }
         
         // Validate that there is sufficient available to lock
-        if total_amount > available_value {
+        if total_amount > available_value + guarantor_available {
             log!(&env, "Error: Insufficient available balance {} > {}", 
-                total_amount, available_value);
+                total_amount, available_value + guarantor_available);
             return Err(ContractError::InsufficientAvailable);
         }
         
+        // The user locks first, the guarantor covers the rest
+        let user_amount = total_amount.min(available_value);
+        let guarantor_amount = total_amount - user_amount;
+        
         // Calculate how many shares need to be locked
-        let shares_needed = buffer_client.shares_for_amount(&total_amount);
+        let shares_needed = if user_amount > 0 {
+            buffer_client.shares_for_amount(&user_amount)
+        } else {
+            0
+        };
         
-        if shares_needed <= 0 {
+        let guarantor_shares = if guarantor_amount > 0 {
+            buffer_client.shares_for_amount(&guarantor_amount)
+        } else {
+            0
+        };
+        
+        if shares_needed < 0 || guarantor_shares < 0 || shares_needed + guarantor_shares <= 0 {
             log!(&env, "Error: Invalid shares calculation");
             return Err(ContractError::InvalidShares);
         }
         
         // ===== LOCK SHARES IN BUFFER =====
         
-        let _lock_result = buffer_client.lock_shares(&user, &shares_needed);
+        // Liens expire after the last due date (or the escrow release) plus a grace period
+        let mut last_due_date = delivery_deadline.unwrap_or(0);
+        for date in due_dates.iter() {
+            last_due_date = last_due_date.max(date);
+        }
+        let lien_expires_at = Self::lien_expiry(&env, last_due_date)?;
+        
+        let lien_id = if shares_needed > 0 {
+            buffer_client.lock_shares(
+                &env.current_contract_address(), &user, &shares_needed, &Some(lien_expires_at),
+            ).lien_id
+        } else {
+            0
+        };
+        
+        let guarantor_lien_id = match &guarantor {
+            Some(guarantor) if guarantor_shares > 0 => {
+                buffer_client.lock_shares(
+                    &env.current_contract_address(), guarantor, &guarantor_shares, &Some(lien_expires_at),
+                ).lien_id
+            }
+            _ => 0,
+        };
         
         // ===== GENERATE UNIQUE PLAN ID =====
         
@@ -294,10 +933,8 @@ impl BridgeContract {
             .get(&DataKey::PlanCounter)
             .unwrap_or(0);
         
-        // Create ID from bytes (avoids issues with to_string())
-        let mut id_bytes = [0u8; 16];
-        id_bytes[0..8].copy_from_slice(&counter.to_be_bytes());
-        let plan_id = String::from_bytes(&env, &id_bytes);
+        // Sequence numbers start at 1: PLN-000001, PLN-000002, ...
+        let plan_id = Self::format_id(&env, PLAN_ID_PREFIX, counter + 1);
         
         // Increment counter for next plan
         env.storage()
@@ -325,7 +962,6 @@ impl BridgeContract {
                 amount,
                 due_date: due_dates.get(i).unwrap(),
                 paid_at: None,
-                payment_source: None,
                 status: InstallmentStatus::Pending,
             };
             
@@ -348,24 +984,46 @@ impl BridgeContract {
             protected_shares: shares_needed,  // Initially all shares are protected
             status: PlanStatus::Active,
             created_at: current_time,
+            schedule_history: Vec::new(&env),
+            forbearance_until: 0,
+            forbearance_count: 0,
+            forbearance_days: 0,
+            payment_preference: Self::default_payment_preference(&env),
+            metadata: metadata.clone(),
+            auth_path: auth_path.clone(),
+            payees: Vec::new(&env),
+            guarantor: guarantor.clone(),
+            guarantor_shares,
+            lien_id,
+            guarantor_lien_id,
+            lien_expires_at,
         };
         
         // Save plan in persistent storage
-        env.storage()
-            .persistent()
-            .set(&DataKey::Plan(plan_id.clone()), &plan);
+        Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
+        
+        // Index the merchant's order reference
+        if let Some(order_ref) = metadata.order_ref.clone() {
+            Self::write_persistent(&env, &DataKey::OrderRef(merchant.clone(), order_ref), &plan_id);
+        }
+        
+        if let Some(delivery_deadline) = delivery_deadline {
+            let escrow = Escrow {
+                delivery_deadline,
+                delivered_at: None,
+                held_amount: 0,
+            };
+            
+            Self::write_persistent(&env, &DataKey::Escrow(plan_id.clone()), &escrow);
+        }
         
         // Add plan to user's plan list
-        let mut user_plans: Vec<String> = env.storage()
-            .persistent()
-            .get(&DataKey::UserPlans(user.clone()))
+        let mut user_plans: Vec<String> = Self::read_persistent(&env, &DataKey::UserPlans(user.clone()))
             .unwrap_or(Vec::new(&env));
         
         user_plans.push_back(plan_id.clone());
         
-        env.storage()
-            .persistent()
-            .set(&DataKey::UserPlans(user.clone()), &user_plans);
+        Self::write_persistent(&env, &DataKey::UserPlans(user.clone()), &user_plans);
         
         // ===== EMIT EVENT =====
         
@@ -377,7 +1035,16 @@ impl BridgeContract {
             total_amount,
             installments_count,
             shares_needed,
-        ), ());
+        ), (auth_path, metadata));
+        
+        if let Some(guarantor) = guarantor {
+            env.events().publish((
+                symbol_short!("guarantee"),
+                plan_id.clone(),
+                guarantor,
+                guarantor_shares,
+            ), ());
+        }
         
         log!(&env, "Bridge plan created with {} shares locked", shares_needed);
         
@@ -386,42 +1053,106 @@ impl BridgeContract {
     
     /// Query a plan by its ID
     pub fn get_plan(env: Env, plan_id: String) -> Result<BridgePlan, ContractError> {
-        env.storage()
-            .persistent()
-            .get(&DataKey::Plan(plan_id))
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::Plan(plan_id))
             .ok_or(ContractError::PlanNotFound)
     }
     
+    /// Query a plan by the merchant's order reference
+    pub fn get_plan_by_order_ref(
+        env: Env,
+        merchant: Address,
+        order_ref: String,
+    ) -> Result<BridgePlan, ContractError> {
+        Self::extend_instance(&env);
+        let plan_id: String = Self::read_persistent(&env, &DataKey::OrderRef(merchant, order_ref))
+            .ok_or(ContractError::PlanNotFound)?;
+        
+        Self::get_plan(env, plan_id)
+    }
+    
     /// Get all plans for a user
     pub fn get_user_plans(env: Env, user: Address) -> Vec<String> {
-        env.storage()
-            .persistent()
-            .get(&DataKey::UserPlans(user))
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::UserPlans(user))
             .unwrap_or(Vec::new(&env))
     }
     
+    /// Extend the storage TTL of a plan and everything attached to it
+    ///
+    /// Reads and writes already bump what they touch; this lets anyone keep
+    /// a quiet plan (e.g. a long forbearance) from being archived.
+    pub fn bump_plan(env: Env, plan_id: String) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
+        let plan = Self::get_plan(env.clone(), plan_id.clone())?;
+        
+        Self::extend_persistent(&env, &DataKey::UserPlans(plan.user.clone()));
+        Self::extend_persistent(&env, &DataKey::Escrow(plan_id.clone()));
+        Self::extend_persistent(&env, &DataKey::Dispute(plan_id.clone()));
+        
+        if let Some(order_ref) = plan.metadata.order_ref.clone() {
+            Self::extend_persistent(&env, &DataKey::OrderRef(plan.merchant.clone(), order_ref));
+        }
+        
+        for installment in plan.installments.iter() {
+            Self::extend_persistent(&env, &DataKey::Receipt(plan_id.clone(), installment.number));
+        }
+        
+        Ok(())
+    }
+    
     /// Collect an installment (called by automatic worker)
     /// 
-    /// Attempts to collect an overdue installment. First tries from available shares,
-    /// if insufficient falls back to protected shares. If still insufficient,
-    /// marks the installment failed and plan as defaulted.
+    /// Attempts to collect an overdue installment, trying each source in the
+    /// plan's payment preference order (wallet, available, protected by default).
+    /// If no source is sufficient, marks the installment failed and plan as
+    /// defaulted. Returns the receipt stored for the installment.
+    ///
+    /// Anyone can collect a due installment. The keeper signs the call and
+    /// receives the configured bounty from the bounty pool when the
+    /// installment is paid; nothing is paid for installments not yet due.
+    ///
+    /// On escrow plans awaiting delivery the installment is only marked as
+    /// held: nothing is paid, None is returned and the receipt is stored
+    /// by `release_escrow`.
     pub fn collect_installment(
         env: Env,
         plan_id: String,             // Plan ID
         installment_number: u32,     // Installment number to collect
-        buffer_contract: Address,    // Buffer Contract address
         merchant_address: Address,   // Merchant address (receives payment)
-    ) -> Result<PaymentSource, ContractError> {
+        keeper: Address,             // Caller receiving the keeper bounty
+    ) -> Result<PaymentReceipt, ContractError> {
+        Self::extend_instance(&env);
         
         // ===== GET AND VALIDATE PLAN =====
         
-        let mut plan: BridgePlan = env.storage()
-            .persistent()
-            .get(&DataKey::Plan(plan_id.clone()))
+        let mut plan: BridgePlan = Self::read_persistent(&env, &DataKey::Plan(plan_id.clone()))
             .ok_or(ContractError::PlanNotFound)?;
         
-        // Verify user authentication
-        plan.user.require_auth();
+        // Collection is permissionless, the keeper signs to claim the bounty
+        keeper.require_auth();
+        
+        // Payments can only go to the plan's merchant
+        if merchant_address != plan.merchant {
+            log!(&env, "Error: Merchant address does not match the plan");
+            return Err(ContractError::Unauthorized);
+        }
+        
+        // Disputed or cancelled plans cannot be collected
+        if plan.status == PlanStatus::InDispute {
+            log!(&env, "Error: Plan in dispute");
+            return Err(ContractError::PlanInDispute);
+        }
+        
+        if plan.status == PlanStatus::Cancelled {
+            log!(&env, "Error: Plan cancelled");
+            return Err(ContractError::PlanNotActive);
+        }
+        
+        if env.ledger().timestamp() < plan.forbearance_until {
+            log!(&env, "Error: Plan in forbearance until {}", plan.forbearance_until);
+            return Err(ContractError::InForbearance);
+        }
         
         // Search for installment in plan
         let installment_index = installment_number - 1;
@@ -447,90 +1178,95 @@ impl BridgeContract {
             return Err(ContractError::NotDueYet);
         }
         
-        // ===== CALCULATE NEEDED SHARES AND GET BALANCE =====
+        // Until delivery the bridge keeps the payment in custody
+        let escrow: Option<Escrow> = Self::read_persistent::<Escrow>(&env, &DataKey::Escrow(plan_id.clone()))
+            .filter(|escrow| escrow.delivered_at.is_none());
         
-        let buffer_client = BufferContractClient::new(&env, &buffer_contract);
-        let shares_needed = buffer_client.shares_for_amount(&installment.amount);
-        let balance = buffer_client.get_balance(&plan.user);
+        let payee = if escrow.is_some() {
+            env.current_contract_address()
+        } else {
+            merchant_address
+        };
         
-        // ===== ATTEMPT COLLECTION (Available first, Protected as fallback) =====
+        // ===== ATTEMPT COLLECTION (in the plan's payment preference order) =====
         
-        let payment_source = if balance.available_shares >= shares_needed {
-            
-            // CASE 1: Collect from available shares
-            buffer_client.debit_available(&plan.user, &shares_needed, &merchant_address);
-            
-            // Update protected shares proportionally
-            if plan.total_amount > 0 {
-                let shares_to_unlock = (shares_needed as i128)
-                    .checked_mul(plan.total_shares)
-                    .unwrap_or(0)
-                    .checked_div(plan.total_amount)
-                    .unwrap_or(0);
+        let buffer_client = Self::buffer_client(&env)?;
+        let paid_by = Self::pay_from_sources(&env, &mut plan, &buffer_client, installment.amount, &payee);
+        
+        let (payment_source, shares_burned, amounts_received) = match paid_by {
+            Some(paid) => paid,
+            None => {
+                // Insufficient funds in every source - Mark as failed
+                log!(&env, "Error: Insufficient funds for installment {}", installment_number);
+                installment.status = InstallmentStatus::Failed;
+                plan.status = PlanStatus::Defaulted;
+                
+                plan.installments.set(installment_index, installment);
+                Self::write_persistent(&env, &DataKey::Plan(plan_id), &plan);
                 
-                plan.protected_shares = plan.protected_shares.checked_sub(shares_to_unlock)
-                    .unwrap_or(0);
+                return Err(ContractError::InsufficientFunds);
             }
+        };
+        
+        // ===== PAY KEEPER BOUNTY =====
+        
+        let fee_amount = Self::pay_keeper_bounty(&env, &plan_id, &keeper, installment.amount);
+        
+        // ===== RECORD RECEIPT =====
+        
+        let mut merchant_amount: i128 = 0;
+        for amount in amounts_received.iter() {
+            merchant_amount += amount;
+        }
+        
+        let receipt = PaymentReceipt {
+            installment_number,
+            source: payment_source,
+            shares_burned,
+            amounts_received,
+            merchant_amount,
+            fee_amount,
+            paid_at: current_time,
+        };
+        
+        Self::write_persistent(&env, &DataKey::Receipt(plan_id.clone(), installment_number), &receipt);
+        
+        // ===== HOLD IN ESCROW UNTIL DELIVERY =====
+        
+        if let Some(mut escrow) = escrow {
+            escrow.held_amount = escrow.held_amount.checked_add(merchant_amount)
+                .ok_or(ContractError::Overflow)?;
+            installment.status = InstallmentStatus::Held;
             
-            log!(&env, "Collected from Available: {} shares", shares_needed);
-            PaymentSource::available()
-            
-        } else if balance.protected_shares >= shares_needed {
-            
-            // CASE 2: Fallback - Collect from protected shares
-            buffer_client.debit_protected(&plan.user, &shares_needed, &merchant_address);
-            
-            // Reduce plan's protected shares
-            plan.protected_shares = plan.protected_shares.checked_sub(shares_needed)
-                .unwrap_or_else(|| {
-                    log!(&env, "Error: Shares protegidos insuficientes");
-                    0
-                });
-            
-            log!(&env, "Collected from Protected: {} shares", shares_needed);
-            PaymentSource::protected() 
-            
-        } else {
+            plan.installments.set(installment_index, installment);
             
-            // CASE 3: Insufficient funds - Mark as failed
-            log!(&env, "Error: Insufficient funds for installment {}", installment_number);
-            installment.status = InstallmentStatus::Failed;
-            plan.status = PlanStatus::Defaulted;
+            Self::write_persistent(&env, &DataKey::Escrow(plan_id.clone()), &escrow);
+            Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
             
-            plan.installments.set(installment_index, installment);
-            env.storage().persistent().set(&DataKey::Plan(plan_id), &plan);
+            env.events().publish((
+                symbol_short!("inst_held"),
+                plan_id,
+                installment_number,
+                merchant_amount,
+            ), ());
             
-            return Err(ContractError::InsufficientFunds);
-        };
+            return Ok(receipt);
+        }
         
         // ===== UPDATE INSTALLMENT STATUS =====
         
         installment.paid_at = Some(current_time);
-        installment.payment_source = Some(payment_source);
         installment.status = InstallmentStatus::Paid;
         
         plan.installments.set(installment_index, installment);
         
         // ===== CHECK IF PLAN IS COMPLETE =====
         
-        let all_paid = (0..plan.installments.len()).all(|i| {
-            plan.installments.get(i).unwrap().status == InstallmentStatus::Paid
-        });
-        
-        if all_paid {
-            plan.status = PlanStatus::Completed;
-            
-            // Release remaining protected shares (if any)
-            if plan.protected_shares > 0 {
-                buffer_client.unlock_shares(&plan.user, &plan.protected_shares);
-                log!(&env, "Released {} remaining shares", plan.protected_shares);
-                plan.protected_shares = 0;
-            }
-        }
+        Self::complete_if_settled(&env, &mut plan, &buffer_client);
         
         // ===== SAVE UPDATED PLAN =====
         
-        env.storage().persistent().set(&DataKey::Plan(plan_id.clone()), &plan);
+        Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
         
         // ===== EMITIR EVENTO =====
         
@@ -539,205 +1275,3345 @@ impl BridgeContract {
             plan_id,
             installment_number,
             payment_source,
-            shares_needed,
+            shares_burned,
+            merchant_amount,
         ), ());
         
-        Ok(payment_source)
+        Ok(receipt)
     }
-    
-    /// Get the next due installment of a plan
-    /// 
-    /// Searches for the first installment that is pending and already due.
-    /// Useful for automatic workers that process collections.
-    pub fn get_next_due(env: Env, plan_id: String) -> Result<Option<Installment>, ContractError> {
-        let plan: BridgePlan = env.storage()
-            .persistent()
-            .get(&DataKey::Plan(plan_id))
-            .ok_or(ContractError::PlanNotFound)?;
-        
+
+    /// Release the installments held in escrow to the merchant
+    ///
+    /// The buyer calls this to confirm delivery. Once the delivery deadline
+    /// has passed anyone may call it. Each held installment is paid in the
+    /// plan's payment preference order and later installments are no longer
+    /// held. Fails without paying anything if a held installment cannot be
+    /// fully covered.
+    pub fn release_escrow(
+        env: Env,
+        plan_id: String,             // Plan ID
+    ) -> Result<i128, ContractError> {
+        Self::extend_instance(&env);
+
+        let mut plan = Self::get_plan(env.clone(), plan_id.clone())?;
+        let mut escrow = Self::get_escrow(env.clone(), plan_id.clone())?;
+
+        if escrow.delivered_at.is_some() {
+            log!(&env, "Error: Escrow already released");
+            return Err(ContractError::PlanNotActive);
+        }
+
+        if plan.status == PlanStatus::InDispute {
+            log!(&env, "Error: Plan in dispute");
+            return Err(ContractError::PlanInDispute);
+        }
+
+        if plan.status == PlanStatus::Cancelled {
+            log!(&env, "Error: Plan cancelled");
+            return Err(ContractError::PlanNotActive);
+        }
+
         let current_time = env.ledger().timestamp();
-        
-        // Search for first pending and due installment
+
+        // Before the deadline only the buyer can confirm delivery
+        if current_time < escrow.delivery_deadline {
+            plan.user.require_auth();
+        }
+
+        let buffer_client = Self::buffer_client(&env)?;
+        let released_amount = escrow.held_amount;
+
         for i in 0..plan.installments.len() {
-            let installment = plan.installments.get(i).unwrap();
-            if installment.status == InstallmentStatus::Pending 
-                && installment.due_date <= current_time {
-                return Ok(Some(installment));
+            let mut installment = plan.installments.get(i).unwrap();
+            if installment.status == InstallmentStatus::Held {
+                installment.paid_at = Some(current_time);
+                installment.status = InstallmentStatus::Paid;
+                plan.installments.set(i, installment);
             }
         }
-        
-        // No due installments
-        Ok(None)
-    }
-    
-    /// Get complete plan summary with updated Buffer values
-    /// 
-    /// Returns the plan along with the current token values of the user's Buffer
-    /// (available and protected). Useful for displaying in UI.
-    pub fn get_plan_summary(
-        env: Env, 
-        plan_id: String, 
-        buffer_contract: Address
-    ) -> Result<(BridgePlan, i128, i128), ContractError> {
-        let plan = Self::get_plan(env.clone(), plan_id)?;
-        
-        let buffer_client = BufferContractClient::new(&env, &buffer_contract);
-        let (available_value, protected_value, _total_value) = buffer_client.get_values(&plan.user);
-        
-        // Returns: (plan, available_value, protected_value)
-        Ok((plan, available_value, protected_value))
+
+        // The held payments were collected when due; only the payout is left
+        if released_amount > 0 {
+            let token_client = TokenClient::new(&env, &Self::get_payment_token(&env)?);
+            for (payee, part) in Self::split_to_payees(&env, &plan, released_amount, &plan.merchant).iter() {
+                if part > 0 {
+                    token_client.transfer(&env.current_contract_address(), &payee, &part);
+                }
+            }
+        }
+
+        escrow.held_amount = 0;
+        escrow.delivered_at = Some(current_time);
+
+        Self::complete_if_settled(&env, &mut plan, &buffer_client);
+
+        Self::write_persistent(&env, &DataKey::Escrow(plan_id.clone()), &escrow);
+        Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
+
+        env.events().publish((
+            symbol_short!("esc_rel"),
+            plan_id,
+            plan.merchant,
+            released_amount,
+        ), ());
+
+        Ok(released_amount)
     }
 
+    /// Query the delivery escrow of a plan
+    pub fn get_escrow(env: Env, plan_id: String) -> Result<Escrow, ContractError> {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::Escrow(plan_id))
+            .ok_or(ContractError::NotEscrowPlan)
     }
 
-// ============ TESTS WITH MOCK BUFFER ============
+    /// Restructure the remaining schedule of a plan
+    ///
+    /// Replaces the pending installments with a new schedule agreed by both
+    /// user and merchant. The new amounts must add up to the unpaid balance;
+    /// paid installments are kept as they are and the previous schedule is
+    /// archived in the plan's history.
+    pub fn restructure_plan(
+        env: Env,
+        plan_id: String,             // Plan ID
+        new_due_dates: Vec<u64>,     // Due dates of the new pending installments
+        new_amounts: Vec<i128>,      // Amounts of the new pending installments
+    ) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
 
+        let mut plan: BridgePlan = Self::read_persistent(&env, &DataKey::Plan(plan_id.clone()))
+            .ok_or(ContractError::PlanNotFound)?;
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use soroban_sdk::{testutils::Address as _, testutils::Ledger, Env, Vec as SorobanVec};
-    
-    // Simple MOCK without complex types
-    #[contract]
-    pub struct MockBuffer;
+        // Both parties must agree to the new terms
+        plan.user.require_auth();
+        plan.merchant.require_auth();
 
-    #[contractimpl]
-    impl MockBuffer {
-        pub fn get_balance(_env: Env, _user: Address) -> (i128, i128, i128) {
-            (10000, 0, 10000) // (available, protected, total)
+        if plan.status != PlanStatus::Active && plan.status != PlanStatus::Restructured {
+            log!(&env, "Error: Plan is not active");
+            return Err(ContractError::PlanNotActive);
         }
 
-        pub fn lock_shares(_env: Env, _user: Address, shares: i128) -> (i128, i128, i128) {
-            (shares, 10000 - shares, shares)
-        }
+        // ===== VALIDATE NEW SCHEDULE =====
 
-        pub fn unlock_shares(_env: Env, _user: Address, shares: i128) -> (i128, i128, i128) {
-            (shares, 10000 + shares, 0)
+        if new_amounts.is_empty() {
+            log!(&env, "Error: Empty schedule");
+            return Err(ContractError::InvalidInstallments);
         }
 
-        pub fn debit_available(_env: Env, _user: Address, shares: i128, _to: Address) -> (i128, i128, bool) {
-            (shares, 10000 - shares, false)
+        if new_due_dates.len() != new_amounts.len() {
+            log!(&env, "Error: Number of dates {} does not match amounts {}",
+                new_due_dates.len(), new_amounts.len());
+            return Err(ContractError::DatesMismatch);
         }
 
-        pub fn debit_protected(_env: Env, _user: Address, shares: i128, _to: Address) -> (i128, i128, bool) {
-            (shares, 10000, true)
-        }
+        // Settled installments must form a prefix so numbering stays positional
+        let mut paid_count: u32 = 0;
+        let mut unpaid_balance: i128 = 0;
+        let mut previous_date: u64 = 0;
 
-        pub fn get_values(_env: Env, _user: Address) -> (i128, i128, i128) {
-            (10000, 0, 10000)
+        for i in 0..plan.installments.len() {
+            let installment = plan.installments.get(i).unwrap();
+            if installment.status != InstallmentStatus::Pending {
+                if paid_count != i {
+                    log!(&env, "Error: Installment {} paid out of order", installment.number);
+                    return Err(ContractError::InvalidInstallments);
+                }
+                paid_count += 1;
+                previous_date = installment.due_date;
+            } else {
+                unpaid_balance += installment.amount;
+            }
         }
 
-        pub fn shares_for_amount(_env: Env, amount: i128) -> i128 {
-            amount
-        }
-    }
+        let new_count = paid_count + new_amounts.len();
 
-    pub struct TestContext {
-        pub env: Env,
-        pub user: Address,
-        pub merchant: Address,
-        pub buffer: Address,
-        pub bridge: Address,
-    }
+        if new_count > 12 {
+            log!(&env, "Error: Too many installments {}", new_count);
+            return Err(ContractError::TooManyInstallments);
+        }
 
-    impl TestContext {
-        pub fn new() -> Self {
-            let env = Env::default();
-            env.mock_all_auths();
-            env.ledger().set_timestamp(1000);
+        let current_time = env.ledger().timestamp();
+        let mut new_total: i128 = 0;
 
-            let buffer = env.register(MockBuffer, ());
-            let bridge = env.register(BridgeContract, ());
+        for i in 0..new_amounts.len() {
+            let amount = new_amounts.get(i).unwrap();
+            if amount <= 0 {
+                log!(&env, "Error: Invalid amount {}", amount);
+                return Err(ContractError::InvalidAmount);
+            }
 
-            Self {
-                env: env.clone(),
-                user: Address::generate(&env),
-                merchant: Address::generate(&env),
-                buffer,
-                bridge,
+            // Strictly increasing, and after the installments already settled
+            let date = new_due_dates.get(i).unwrap();
+            if date <= current_time || date <= previous_date {
+                log!(&env, "Error: Invalid due date {}", date);
+                return Err(ContractError::InvalidDueDate);
             }
+            previous_date = date;
+
+            new_total = new_total.checked_add(amount)
+                .ok_or(ContractError::InvalidAmount)?;
         }
 
-        pub fn client(&self) -> BridgeContractClient {
-            BridgeContractClient::new(&self.env, &self.bridge)
+        if new_total != unpaid_balance {
+            log!(&env, "Error: New amounts {} do not match unpaid balance {}",
+                new_total, unpaid_balance);
+            return Err(ContractError::AmountsMismatch);
         }
 
-        pub fn advance_time(&self, seconds: u64) {
-            self.env.ledger().set_timestamp(self.env.ledger().timestamp() + seconds);
+        // ===== BUILD NEW SCHEDULE =====
+
+        let mut installments: Vec<Installment> = plan.installments.slice(0..paid_count);
+
+        for i in 0..new_amounts.len() {
+            installments.push_back(Installment {
+                number: paid_count + i + 1,
+                amount: new_amounts.get(i).unwrap(),
+                due_date: new_due_dates.get(i).unwrap(),
+                paid_at: None,
+                status: InstallmentStatus::Pending,
+            });
         }
-    }
 
-    #[test]
-    fn test_create_plan_basic() {
-        let ctx = TestContext::new();
-        let client = ctx.client();
+        plan.schedule_history.push_back(ScheduleRevision {
+            installments: plan.installments.clone(),
+            replaced_at: current_time,
+        });
 
-        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
-        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &3, &due_dates, &ctx.buffer);
-        let plan = client.get_plan(&plan_id);
+        plan.installments = installments;
+        plan.installments_count = new_count;
+        plan.status = PlanStatus::Restructured;
 
-        assert_eq!(plan.user, ctx.user);
-        assert_eq!(plan.merchant, ctx.merchant);
-        assert_eq!(plan.total_amount, 3000);
-        assert_eq!(plan.installments.len(), 3);
+        let buffer_client = Self::buffer_client(&env)?;
+        let lien_expires_at = Self::lien_expiry(&env, Self::last_due_date(&plan))?;
+        Self::extend_plan_liens(&env, &mut plan, &buffer_client, lien_expires_at);
+
+        Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
+
+        env.events().publish((
+            symbol_short!("plan_rstr"),
+            plan_id,
+            paid_count,
+            new_count,
+            unpaid_balance,
+        ), ());
+
+        Ok(())
     }
 
-    #[test]
-    fn test_full_lifecycle() {
-        let ctx = TestContext::new();
-        let client = ctx.client();
+    /// Open a dispute on a plan
+    ///
+    /// Called by the buyer. Freezes collection of the plan until the
+    /// merchant resolves the dispute.
+    pub fn open_dispute(
+        env: Env,
+        plan_id: String,             // Plan ID
+        reason: u32,                 // Reason code for support tooling
+    ) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
 
-        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
-        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &3, &due_dates, &ctx.buffer);
+        let mut plan: BridgePlan = Self::read_persistent(&env, &DataKey::Plan(plan_id.clone()))
+            .ok_or(ContractError::PlanNotFound)?;
 
-        ctx.advance_time(1500);
-        let source = client.collect_installment(&plan_id, &1, &ctx.buffer, &ctx.merchant);
-        assert_eq!(source.to_u32(), 0);
+        plan.user.require_auth();
 
-        ctx.advance_time(1000);
-        client.collect_installment(&plan_id, &2, &ctx.buffer, &ctx.merchant);
+        if plan.status == PlanStatus::InDispute {
+            log!(&env, "Error: Plan already in dispute");
+            return Err(ContractError::PlanInDispute);
+        }
 
-        ctx.advance_time(1000);
-        client.collect_installment(&plan_id, &3, &ctx.buffer, &ctx.merchant);
+        if plan.status != PlanStatus::Active && plan.status != PlanStatus::Restructured {
+            log!(&env, "Error: Plan is not active");
+            return Err(ContractError::PlanNotActive);
+        }
 
-        let final_plan = client.get_plan(&plan_id);
-        assert_eq!(final_plan.status, PlanStatus::Completed);
-    }
+        let current_time = env.ledger().timestamp();
 
-    #[test]
-    fn test_ltv_max_allowed() {
-        let ctx = TestContext::new();
-        let client = ctx.client();
+        let dispute = Dispute {
+            reason,
+            opened_at: current_time,
+            prior_status: plan.status.clone(),
+        };
+        plan.status = PlanStatus::InDispute;
 
-        // Buffer total = 10000, LTV 80% = 8000 maximum allowed
-        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
-        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &8000, &3, &due_dates, &ctx.buffer);
-        let plan = client.get_plan(&plan_id);
+        Self::write_persistent(&env, &DataKey::Dispute(plan_id.clone()), &dispute);
+        Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
 
-        assert_eq!(plan.total_amount, 8000);
-        assert_eq!(plan.status, PlanStatus::Active);
+        env.events().publish((
+            symbol_short!("disp_open"),
+            plan_id,
+            plan.user,
+            plan.merchant,
+            reason,
+        ), ());
+
+        Ok(())
     }
 
-    #[test]
-    #[should_panic(expected = "Status(ContractError(15))")] // ExceedsMaxLTV
-    fn test_ltv_exceeds_maximum() {
-        let ctx = TestContext::new();
-        let client = ctx.client();
+    /// Resolve an open dispute
+    ///
+    /// Called by the merchant. `Continue` restores the plan as it was,
+    /// `PartialRefund` forgives part of the unpaid balance (from the last
+    /// installments backwards) and releases the matching collateral, and
+    /// `FullCancel` cancels the plan releasing all remaining collateral.
+    /// On escrow plans, any outcome other than `Continue` also returns the
+    /// held installments to the buyer's wallet.
+    pub fn resolve_dispute(
+        env: Env,
+        plan_id: String,             // Plan ID
+        outcome: DisputeOutcome,     // Resolution decided by the merchant
+    ) -> Result<i128, ContractError> {
+        Self::extend_instance(&env);
 
-        // Attempt to create plan for 9000 when maximum is 8000 (80% of 10000)
-        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
-        client.create_plan(&ctx.user, &ctx.merchant, &9000, &3, &due_dates, &ctx.buffer);
-    }
+        let mut plan: BridgePlan = Self::read_persistent(&env, &DataKey::Plan(plan_id.clone()))
+            .ok_or(ContractError::PlanNotFound)?;
 
-    #[test]
-    #[should_panic(expected = "Status(ContractError(15))")] // ExceedsMaxLTV
-    fn test_ltv_at_100_percent_fails() {
-        let ctx = TestContext::new();
-        let client = ctx.client();
+        plan.merchant.require_auth();
 
-        // Attempting to use 100% of buffer (10000) should fail
-        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
-        client.create_plan(&ctx.user, &ctx.merchant, &10000, &3, &due_dates, &ctx.buffer);
-    }
-}
\ No newline at end of file
+        let dispute = Self::get_dispute(env.clone(), plan_id.clone())?;
+
+        let buffer_client = Self::buffer_client(&env)?;
+        Self::sync_plan_collateral(&env, &mut plan, &buffer_client);
+
+        // Held escrow payments go back to the buyer
+        if outcome != DisputeOutcome::Continue {
+            Self::refund_escrow(&env, &plan_id, &mut plan)?;
+        }
+
+        // Shares released back to the user's available balance
+        let shares_released = match outcome.clone() {
+
+            DisputeOutcome::Continue => {
+                plan.status = dispute.prior_status;
+                0
+            }
+
+            DisputeOutcome::PartialRefund(refund_amount) => {
+                let mut unpaid_balance: i128 = 0;
+                for i in 0..plan.installments.len() {
+                    let installment = plan.installments.get(i).unwrap();
+                    if installment.status == InstallmentStatus::Pending {
+                        unpaid_balance += installment.amount;
+                    }
+                }
+
+                // A refund of the whole balance is a cancellation
+                if refund_amount <= 0 || refund_amount >= unpaid_balance {
+                    log!(&env, "Error: Invalid refund {} for unpaid balance {}",
+                        refund_amount, unpaid_balance);
+                    return Err(ContractError::InvalidAmount);
+                }
+
+                // Forgive from the last pending installment backwards
+                let mut remaining = refund_amount;
+                let mut i = plan.installments.len();
+                while remaining > 0 && i > 0 {
+                    i -= 1;
+                    let mut installment = plan.installments.get(i).unwrap();
+                    if installment.status != InstallmentStatus::Pending {
+                        continue;
+                    }
+
+                    let reduction = remaining.min(installment.amount);
+                    installment.amount -= reduction;
+                    remaining -= reduction;
+
+                    if installment.amount == 0 {
+                        installment.status = InstallmentStatus::Waived;
+                    }
+                    plan.installments.set(i, installment);
+                }
+
+                // Release collateral in proportion to the forgiven amount
+                let shares_to_unlock = plan.protected_shares
+                    .checked_mul(refund_amount)
+                    .ok_or(ContractError::InvalidShares)?
+                    / unpaid_balance;
+
+                if shares_to_unlock > 0 {
+                    buffer_client.unlock_shares(&plan.user, &plan.lien_id, &shares_to_unlock);
+                    plan.protected_shares -= shares_to_unlock;
+                }
+
+                // The guarantor's collateral shrinks in the same proportion
+                let guarantor_to_unlock = plan.guarantor_shares
+                    .checked_mul(refund_amount)
+                    .ok_or(ContractError::InvalidShares)?
+                    / unpaid_balance;
+
+                if let Some(guarantor) = &plan.guarantor {
+                    if guarantor_to_unlock > 0 {
+                        buffer_client.unlock_shares(guarantor, &plan.guarantor_lien_id, &guarantor_to_unlock);
+                        plan.guarantor_shares -= guarantor_to_unlock;
+                        log!(&env, "Released {} guarantor shares", guarantor_to_unlock);
+                    }
+                }
+
+                plan.total_amount -= refund_amount;
+                plan.status = dispute.prior_status;
+                shares_to_unlock
+            }
+
+            DisputeOutcome::FullCancel => {
+                let shares_to_unlock = plan.protected_shares;
+
+                if shares_to_unlock > 0 {
+                    buffer_client.unlock_shares(&plan.user, &plan.lien_id, &shares_to_unlock);
+                    plan.protected_shares = 0;
+                }
+
+                Self::release_guarantor(&env, &mut plan, &buffer_client);
+
+                plan.status = PlanStatus::Cancelled;
+                shares_to_unlock
+            }
+        };
+
+        env.storage().persistent().remove(&DataKey::Dispute(plan_id.clone()));
+        Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
+
+        env.events().publish((
+            symbol_short!("disp_res"),
+            plan_id,
+            plan.user,
+            plan.merchant,
+            outcome,
+            shares_released,
+        ), ());
+
+        log!(&env, "Dispute resolved, {} shares released", shares_released);
+
+        Ok(shares_released)
+    }
+
+    /// Grant a hardship forbearance window on a plan
+    ///
+    /// Called by the admin or the plan's merchant. Pending installments are
+    /// pushed forward by the pause length and nothing can be collected until
+    /// the window ends. Windows per plan and total days are capped by config.
+    pub fn grant_forbearance(
+        env: Env,
+        plan_id: String,             // Plan ID
+        granter: Address,            // Admin or plan merchant
+        days: u32,                   // Length of the pause in days
+    ) -> Result<u64, ContractError> {
+        Self::extend_instance(&env);
+
+        let mut plan: BridgePlan = Self::read_persistent(&env, &DataKey::Plan(plan_id.clone()))
+            .ok_or(ContractError::PlanNotFound)?;
+
+        granter.require_auth();
+
+        if granter != plan.merchant && granter != Self::get_admin(&env) {
+            log!(&env, "Error: Granter is neither admin nor merchant");
+            return Err(ContractError::Unauthorized);
+        }
+
+        if plan.status != PlanStatus::Active && plan.status != PlanStatus::Restructured {
+            log!(&env, "Error: Plan is not active");
+            return Err(ContractError::PlanNotActive);
+        }
+
+        if days == 0 {
+            log!(&env, "Error: Invalid forbearance length {}", days);
+            return Err(ContractError::InvalidAmount);
+        }
+
+        let current_time = env.ledger().timestamp();
+
+        if current_time < plan.forbearance_until {
+            log!(&env, "Error: Forbearance already active until {}", plan.forbearance_until);
+            return Err(ContractError::InForbearance);
+        }
+
+        let config = Self::get_config(env.clone());
+
+        let total_days = plan.forbearance_days.checked_add(days)
+            .ok_or(ContractError::ForbearanceLimitExceeded)?;
+
+        if plan.forbearance_count >= config.max_forbearance_count
+            || total_days > config.max_forbearance_days {
+            log!(&env, "Error: Forbearance limit reached ({} windows, {} days)",
+                plan.forbearance_count, plan.forbearance_days);
+            return Err(ContractError::ForbearanceLimitExceeded);
+        }
+
+        // ===== SHIFT PENDING INSTALLMENTS =====
+
+        let pause = (days as u64).checked_mul(SECONDS_PER_DAY)
+            .ok_or(ContractError::InvalidDueDate)?;
+
+        for i in 0..plan.installments.len() {
+            let mut installment = plan.installments.get(i).unwrap();
+            if installment.status == InstallmentStatus::Pending {
+                installment.due_date = installment.due_date.checked_add(pause)
+                    .ok_or(ContractError::InvalidDueDate)?;
+                plan.installments.set(i, installment);
+            }
+        }
+
+        plan.forbearance_until = current_time.checked_add(pause)
+            .ok_or(ContractError::InvalidDueDate)?;
+        plan.forbearance_count += 1;
+        plan.forbearance_days = total_days;
+
+        // Keep the collateral locked past the shifted schedule
+        let buffer_client = Self::buffer_client(&env)?;
+        let lien_expires_at = Self::lien_expiry(&env, Self::last_due_date(&plan))?;
+        Self::extend_plan_liens(&env, &mut plan, &buffer_client, lien_expires_at);
+
+        Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
+
+        env.events().publish((
+            symbol_short!("forbear"),
+            plan_id,
+            granter,
+            days,
+            plan.forbearance_until,
+        ), ());
+
+        Ok(plan.forbearance_until)
+    }
+
+    /// Set the order in which payment sources are tried for a plan
+    ///
+    /// Called by the user. Each source may appear once and protected shares
+    /// must stay in the list, since they back the plan as collateral.
+    pub fn set_payment_preference(
+        env: Env,
+        plan_id: String,             // Plan ID
+        preference: Vec<PaymentSource>, // Sources in the order to try
+    ) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
+
+        let mut plan: BridgePlan = Self::read_persistent(&env, &DataKey::Plan(plan_id.clone()))
+            .ok_or(ContractError::PlanNotFound)?;
+
+        plan.user.require_auth();
+
+        let mut has_protected = false;
+
+        for i in 0..preference.len() {
+            let source = preference.get(i).unwrap();
+
+            if preference.slice(0..i).contains(source) {
+                log!(&env, "Error: Duplicated payment source");
+                return Err(ContractError::InvalidPaymentSource);
+            }
+
+            has_protected |= source == PaymentSource::Protected;
+        }
+
+        if !has_protected {
+            log!(&env, "Error: Protected shares must remain a payment source");
+            return Err(ContractError::InvalidPaymentSource);
+        }
+
+        plan.payment_preference = preference;
+
+        Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
+
+        env.events().publish((
+            symbol_short!("pay_pref"),
+            plan_id,
+            plan.payment_preference,
+        ), ());
+
+        Ok(())
+    }
+
+    /// Split a plan's payments among several payees
+    ///
+    /// Called by the merchant (e.g. seller, platform and shipping). Shares
+    /// are in basis points and must add up to 100%. Every later payment is
+    /// debited straight to each payee; the last payee receives the rounding
+    /// remainder so nothing is left behind. An empty list pays the merchant.
+    pub fn set_plan_payees(
+        env: Env,
+        plan_id: String,             // Plan ID
+        payees: Vec<Payee>,          // Payees and their shares
+    ) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
+
+        let mut plan: BridgePlan = Self::read_persistent(&env, &DataKey::Plan(plan_id.clone()))
+            .ok_or(ContractError::PlanNotFound)?;
+
+        plan.merchant.require_auth();
+
+        if payees.len() > MAX_PAYEES {
+            log!(&env, "Error: Too many payees {}", payees.len());
+            return Err(ContractError::InvalidPayees);
+        }
+
+        let mut total_bps: u32 = 0;
+        for payee in payees.iter() {
+            if payee.bps == 0 {
+                log!(&env, "Error: Payee with zero share");
+                return Err(ContractError::InvalidPayees);
+            }
+            total_bps = total_bps.saturating_add(payee.bps);
+        }
+
+        if !payees.is_empty() && total_bps != 10000 {
+            log!(&env, "Error: Payee shares add up to {} bps", total_bps);
+            return Err(ContractError::InvalidPayees);
+        }
+
+        plan.payees = payees;
+
+        Self::write_persistent(&env, &DataKey::Plan(plan_id.clone()), &plan);
+
+        env.events().publish((
+            symbol_short!("payees"),
+            plan_id,
+            plan.payees,
+        ), ());
+
+        Ok(())
+    }
+
+    /// Get the open dispute of a plan
+    pub fn get_dispute(env: Env, plan_id: String) -> Result<Dispute, ContractError> {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::Dispute(plan_id))
+            .ok_or(ContractError::NotInDispute)
+    }
+
+    /// Get the payment receipt of a paid installment
+    pub fn get_receipt(
+        env: Env,
+        plan_id: String,
+        installment_number: u32,
+    ) -> Result<PaymentReceipt, ContractError> {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::Receipt(plan_id, installment_number))
+            .ok_or(ContractError::InstallmentNotFound)
+    }
+
+    /// Publish a payment request (invoice)
+    ///
+    /// Called by the merchant at checkout. The customer accepts it with
+    /// `accept_invoice`, which creates the plan with the merchant's terms.
+    pub fn create_invoice(
+        env: Env,
+        merchant: Address,           // Merchant publishing the invoice
+        amount: i128,                // Amount to finance
+        templates: Vec<InstallmentTemplate>, // Installment options offered
+        expires_at: u64,             // Expiry timestamp
+        metadata: Option<PlanMetadata>, // Optional order information
+    ) -> Result<String, ContractError> {
+        Self::extend_instance(&env);
+
+        merchant.require_auth();
+
+        if amount <= 0 {
+            log!(&env, "Error: Invalid amount {}", amount);
+            return Err(ContractError::InvalidAmount);
+        }
+
+        if templates.is_empty() {
+            log!(&env, "Error: No installment templates offered");
+            return Err(ContractError::InvalidTemplate);
+        }
+
+        for template in templates.iter() {
+            // The whole schedule plus the lien grace must fit in a buffer lien
+            let span = template.interval_secs.checked_mul(template.installments_count as u64)
+                .and_then(|span| span.checked_add(LIEN_GRACE_SECS));
+            
+            if template.installments_count == 0 || template.installments_count > 12
+                || template.interval_secs == 0
+                || span.is_none_or(|span| span > MAX_LIEN_DURATION_SECS) {
+                log!(&env, "Error: Invalid template {} x {}s",
+                    template.installments_count, template.interval_secs);
+                return Err(ContractError::InvalidTemplate);
+            }
+        }
+
+        let metadata = metadata.unwrap_or(PlanMetadata {
+            order_ref: None,
+            invoice_hash: None,
+            category_code: None,
+            currency_code: None,
+        });
+
+        Self::validate_metadata(&env, &merchant, &metadata)?;
+
+        let current_time = env.ledger().timestamp();
+
+        if expires_at <= current_time {
+            log!(&env, "Error: Expiry in the past {}", expires_at);
+            return Err(ContractError::InvoiceExpired);
+        }
+
+        // An open invoice holds its order ref until it is accepted, cancelled or expires
+        if let Some(order_ref) = &metadata.order_ref {
+            let reserved: Option<String> = Self::read_persistent(
+                &env, &DataKey::InvoiceOrderRef(merchant.clone(), order_ref.clone()));
+            if let Some(other_id) = reserved {
+                let other = Self::get_invoice(env.clone(), other_id)?;
+                if other.status == InvoiceStatus::Open && current_time < other.expires_at {
+                    log!(&env, "Error: Order reference reserved by {}", other.invoice_id);
+                    return Err(ContractError::OrderRefTaken);
+                }
+            }
+        }
+
+        let counter: u64 = env.storage()
+            .instance()
+            .get(&DataKey::InvoiceCounter)
+            .unwrap_or(0);
+
+        let invoice_id = Self::format_id(&env, INVOICE_ID_PREFIX, counter + 1);
+
+        env.storage()
+            .instance()
+            .set(&DataKey::InvoiceCounter, &(counter + 1));
+
+        let invoice = Invoice {
+            invoice_id: invoice_id.clone(),
+            merchant: merchant.clone(),
+            amount,
+            templates,
+            metadata,
+            expires_at,
+            status: InvoiceStatus::Open,
+            plan_id: None,
+            created_at: current_time,
+        };
+
+        Self::write_persistent(&env, &DataKey::Invoice(invoice_id.clone()), &invoice);
+
+        if let Some(order_ref) = invoice.metadata.order_ref {
+            Self::write_persistent(&env, &DataKey::InvoiceOrderRef(merchant.clone(), order_ref), &invoice_id);
+        }
+
+        env.events().publish((
+            symbol_short!("inv_new"),
+            invoice_id.clone(),
+            merchant,
+            amount,
+            expires_at,
+        ), ());
+
+        Ok(invoice_id)
+    }
+
+    /// Accept an invoice, creating the plan with the merchant's terms
+    ///
+    /// Called by the customer. The invoice is single-use: it is marked
+    /// accepted in the same transaction that creates the plan.
+    pub fn accept_invoice(
+        env: Env,
+        invoice_id: String,          // Invoice ID
+        user: Address,               // Customer accepting the invoice
+        template_index: u32,         // Index of the chosen installment template
+    ) -> Result<String, ContractError> {
+        Self::extend_instance(&env);
+
+        user.require_auth();
+
+        let mut invoice = Self::get_invoice(env.clone(), invoice_id.clone())?;
+
+        if invoice.status != InvoiceStatus::Open {
+            log!(&env, "Error: Invoice is not open");
+            return Err(ContractError::InvoiceNotOpen);
+        }
+
+        let current_time = env.ledger().timestamp();
+
+        if current_time >= invoice.expires_at {
+            log!(&env, "Error: Invoice expired at {}", invoice.expires_at);
+            return Err(ContractError::InvoiceExpired);
+        }
+
+        let template = invoice.templates
+            .get(template_index)
+            .ok_or(ContractError::InvalidTemplate)?;
+
+        // Due dates are counted from acceptance
+        let mut due_dates: Vec<u64> = Vec::new(&env);
+        for i in 1..=template.installments_count {
+            let due_date = template.interval_secs.checked_mul(i as u64)
+                .and_then(|offset| current_time.checked_add(offset))
+                .ok_or(ContractError::InvalidTemplate)?;
+            due_dates.push_back(due_date);
+        }
+
+        let options = PlanOptions {
+            metadata: invoice.metadata.clone(),
+            guarantor: None,
+            delivery_deadline: None,
+        };
+
+        let plan_id = Self::create_plan_internal(
+            env.clone(),
+            user.clone(),
+            invoice.merchant.clone(),
+            invoice.amount,
+            due_dates,
+            options,
+            AuthPath::Invoice,
+        )?;
+
+        invoice.status = InvoiceStatus::Accepted;
+        invoice.plan_id = Some(plan_id.clone());
+
+        Self::write_persistent(&env, &DataKey::Invoice(invoice_id.clone()), &invoice);
+        Self::release_invoice_order_ref(&env, &invoice);
+
+        env.events().publish((
+            symbol_short!("inv_acc"),
+            invoice_id,
+            user,
+            plan_id.clone(),
+        ), ());
+
+        Ok(plan_id)
+    }
+
+    /// Cancel an open invoice (merchant only)
+    pub fn cancel_invoice(env: Env, invoice_id: String) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
+        let mut invoice = Self::get_invoice(env.clone(), invoice_id.clone())?;
+
+        invoice.merchant.require_auth();
+
+        if invoice.status != InvoiceStatus::Open {
+            log!(&env, "Error: Invoice is not open");
+            return Err(ContractError::InvoiceNotOpen);
+        }
+
+        invoice.status = InvoiceStatus::Cancelled;
+
+        Self::write_persistent(&env, &DataKey::Invoice(invoice_id.clone()), &invoice);
+        Self::release_invoice_order_ref(&env, &invoice);
+
+        env.events().publish((
+            symbol_short!("inv_cncl"),
+            invoice_id,
+            invoice.merchant,
+        ), ());
+
+        Ok(())
+    }
+
+    /// Query an invoice by its ID
+    pub fn get_invoice(env: Env, invoice_id: String) -> Result<Invoice, ContractError> {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::Invoice(invoice_id))
+            .ok_or(ContractError::InvoiceNotFound)
+    }
+
+    /// Allow or disallow a merchant to receive credit line draws (admin only)
+    pub fn register_credit_merchant(env: Env, merchant: Address, enabled: bool) {
+        Self::extend_instance(&env);
+        let admin = Self::get_admin(&env);
+        admin.require_auth();
+
+        if enabled {
+            Self::write_persistent(&env, &DataKey::CreditMerchant(merchant.clone()), &true);
+        } else {
+            env.storage().persistent().remove(&DataKey::CreditMerchant(merchant.clone()));
+        }
+
+        env.events().publish((symbol_short!("cl_merch"), merchant, enabled), ());
+    }
+
+    /// Open a revolving credit line backed by the user's Buffer
+    pub fn open_credit_line(env: Env, user: Address) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
+        user.require_auth();
+
+        if Self::extend_persistent(&env, &DataKey::CreditLine(user.clone())) {
+            log!(&env, "Error: Credit line already open");
+            return Err(ContractError::CreditLineExists);
+        }
+
+        let current_time = env.ledger().timestamp();
+
+        let line = CreditLine {
+            user: user.clone(),
+            outstanding: 0,
+            locked_shares: 0,
+            draws: Vec::new(&env),
+            statement: CreditStatement {
+                balance: 0,
+                minimum_due: 0,
+                due_date: 0,
+                closed_at: 0,
+            },
+            next_statement_at: current_time + STATEMENT_PERIOD_SECS,
+            opened_at: current_time,
+        };
+
+        Self::write_persistent(&env, &DataKey::CreditLine(user.clone()), &line);
+
+        env.events().publish((symbol_short!("cl_open"), user), ());
+
+        Ok(())
+    }
+
+    /// Get a user's credit line
+    pub fn get_credit_line(env: Env, user: Address) -> Result<CreditLine, ContractError> {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::CreditLine(user))
+            .ok_or(ContractError::CreditLineNotFound)
+    }
+
+    /// Get the amount a user can still draw
+    ///
+    /// The limit is the Buffer total value × MAX_LTV_BPS, minus what is
+    /// already drawn, and never more than the available value to lock.
+    pub fn get_available_credit(
+        env: Env,
+        user: Address,
+    ) -> Result<i128, ContractError> {
+        Self::extend_instance(&env);
+        let line = Self::get_credit_line(env.clone(), user.clone())?;
+
+        let buffer_client = Self::buffer_client(&env)?;
+        let (available_value, _, total_value) = buffer_client.get_values(&user);
+
+        let limit = (total_value * MAX_LTV_BPS) / 10000;
+        let headroom = (limit - line.outstanding).max(0);
+
+        Ok(headroom.min(available_value))
+    }
+
+    /// Draw from the credit line to pay a registered merchant
+    ///
+    /// Locks the matching Buffer shares as collateral. Nothing is paid up
+    /// front: the bridge holds no liquidity of its own, so the merchant is
+    /// settled from the user's shares as each statement is collected, and
+    /// in full once the draw is repaid.
+    pub fn draw_credit(
+        env: Env,
+        user: Address,               // Credit line owner
+        merchant: Address,           // Registered merchant receiving the draw
+        amount: i128,                // Amount to draw in tokens
+    ) -> Result<i128, ContractError> {
+        Self::extend_instance(&env);
+
+        user.require_auth();
+
+        if amount <= 0 {
+            log!(&env, "Error: Invalid amount {}", amount);
+            return Err(ContractError::InvalidAmount);
+        }
+
+        if !Self::extend_persistent(&env, &DataKey::CreditMerchant(merchant.clone())) {
+            log!(&env, "Error: Merchant not registered for credit");
+            return Err(ContractError::MerchantNotRegistered);
+        }
+
+        let available_credit = Self::get_available_credit(env.clone(), user.clone())?;
+
+        if amount > available_credit {
+            log!(&env, "Error: Draw exceeds available credit {} > {}", amount, available_credit);
+            return Err(ContractError::ExceedsCreditLimit);
+        }
+
+        let buffer_client = Self::buffer_client(&env)?;
+        let shares_needed = buffer_client.shares_for_amount(&amount);
+
+        if shares_needed <= 0 {
+            log!(&env, "Error: Invalid shares calculation");
+            return Err(ContractError::InvalidShares);
+        }
+
+        let mut line = Self::get_credit_line(env.clone(), user.clone())?;
+
+        // Locked until the next statement's payment is due; each statement extends it
+        let lien_expires_at = Self::lien_expiry(&env, line.next_statement_at + STATEMENT_GRACE_SECS)?;
+        let lock = buffer_client.lock_shares(
+            &env.current_contract_address(), &user, &shares_needed, &Some(lien_expires_at),
+        );
+
+        line.outstanding += amount;
+        line.locked_shares += shares_needed;
+        line.draws.push_back(CreditDraw {
+            merchant: merchant.clone(),
+            outstanding: amount,
+            locked_shares: shares_needed,
+            lien_id: lock.lien_id,
+            lien_expires_at,
+            drawn_at: env.ledger().timestamp(),
+        });
+
+        Self::write_persistent(&env, &DataKey::CreditLine(user.clone()), &line);
+
+        env.events().publish((
+            symbol_short!("cl_draw"),
+            user,
+            merchant,
+            amount,
+            shares_needed,
+        ), ());
+
+        Ok(shares_needed)
+    }
+
+    /// Close the monthly statement of a credit line (called by worker)
+    ///
+    /// Sets the minimum payment (MIN_PAYMENT_BPS of the balance, or the
+    /// whole balance if smaller than one token unit) collectable after the
+    /// grace period. Any unpaid minimum from the previous cycle carries over.
+    pub fn close_statement(
+        env: Env,
+        user: Address,               // Credit line owner
+    ) -> Result<CreditStatement, ContractError> {
+        Self::extend_instance(&env);
+        let mut line = Self::get_credit_line(env.clone(), user.clone())?;
+
+        let current_time = env.ledger().timestamp();
+
+        if current_time < line.next_statement_at {
+            log!(&env, "Error: Statement not due until {}", line.next_statement_at);
+            return Err(ContractError::StatementNotDue);
+        }
+
+        let mut minimum_due = (line.outstanding * MIN_PAYMENT_BPS) / 10000;
+        if minimum_due == 0 {
+            minimum_due = line.outstanding;
+        }
+        minimum_due = (minimum_due + line.statement.minimum_due).min(line.outstanding);
+
+        line.statement = CreditStatement {
+            balance: line.outstanding,
+            minimum_due,
+            due_date: current_time + STATEMENT_GRACE_SECS,
+            closed_at: current_time,
+        };
+        line.next_statement_at = current_time + STATEMENT_PERIOD_SECS;
+
+        // Unpaid draws stay collateralized through the next statement
+        let buffer_client = Self::buffer_client(&env)?;
+        let lien_expires_at = Self::lien_expiry(&env, line.next_statement_at + STATEMENT_GRACE_SECS)?;
+
+        for i in 0..line.draws.len() {
+            let mut draw = line.draws.get(i).unwrap();
+            if lien_expires_at > draw.lien_expires_at
+                && Self::lien_shares(&env, &buffer_client, draw.lien_id) > 0 {
+                buffer_client.extend_lien(&user, &draw.lien_id, &lien_expires_at);
+                draw.lien_expires_at = lien_expires_at;
+                line.draws.set(i, draw);
+            }
+        }
+
+        Self::write_persistent(&env, &DataKey::CreditLine(user.clone()), &line);
+
+        env.events().publish((
+            symbol_short!("cl_stmt"),
+            user,
+            line.statement.balance,
+            line.statement.minimum_due,
+            line.statement.due_date,
+        ), ());
+
+        Ok(line.statement)
+    }
+
+    /// Collect the minimum payment of a credit line (called by worker)
+    ///
+    /// Pays draws oldest first from available shares, falling back to the
+    /// protected collateral, and releases collateral as the balance drops.
+    pub fn collect_credit(
+        env: Env,
+        user: Address,               // Credit line owner
+    ) -> Result<i128, ContractError> {
+        Self::extend_instance(&env);
+
+        let mut line = Self::get_credit_line(env.clone(), user.clone())?;
+
+        if line.statement.minimum_due <= 0 || env.ledger().timestamp() < line.statement.due_date {
+            log!(&env, "Error: No minimum payment due");
+            return Err(ContractError::StatementNotDue);
+        }
+
+        let buffer_client = Self::buffer_client(&env)?;
+        let mut remaining = line.statement.minimum_due;
+
+        let mut collateral_short = false;
+
+        while remaining > 0 && !line.draws.is_empty() {
+            let mut draw = line.draws.get(0).unwrap();
+            let mut payment = remaining.min(draw.outstanding);
+
+            // A reclaimed or lapsed lien no longer backs the draw
+            let held = Self::lien_shares(&env, &buffer_client, draw.lien_id);
+            if draw.locked_shares > held {
+                line.locked_shares -= draw.locked_shares - held;
+                draw.locked_shares = held;
+            }
+
+            let shares_needed = buffer_client.shares_for_amount(&payment);
+            let balance = buffer_client.get_balance(&user);
+
+            // Collateral backing this payment
+            let collateral = draw.locked_shares
+                .checked_mul(payment)
+                .ok_or(ContractError::InvalidShares)?
+                / draw.outstanding;
+
+            if Self::debitable_shares(&env, &buffer_client, &balance, &user) >= shares_needed {
+                buffer_client.debit_available(&env.current_contract_address(), &user, &shares_needed, &draw.merchant);
+                if collateral > 0 {
+                    buffer_client.unlock_shares(&user, &draw.lien_id, &collateral);
+                }
+                draw.locked_shares -= collateral;
+                line.locked_shares -= collateral;
+            } else {
+                // Take what the lien still holds, even if it falls short
+                let shares = shares_needed.min(draw.locked_shares).min(balance.protected_shares);
+
+                if shares <= 0 {
+                    log!(&env, "No collateral left for credit payment {}", payment);
+                    break;
+                }
+
+                buffer_client.debit_protected(&user, &draw.lien_id, &shares, &draw.merchant);
+                draw.locked_shares -= shares;
+                line.locked_shares -= shares;
+
+                if shares < shares_needed {
+                    payment = payment
+                        .checked_mul(shares)
+                        .ok_or(ContractError::InvalidShares)?
+                        / shares_needed;
+                    collateral_short = true;
+                }
+            }
+
+            draw.outstanding -= payment;
+            line.outstanding -= payment;
+            remaining -= payment;
+
+            if draw.outstanding == 0 {
+                // Draw repaid: release whatever collateral is left
+                if draw.locked_shares > 0 {
+                    buffer_client.unlock_shares(&user, &draw.lien_id, &draw.locked_shares);
+                    line.locked_shares -= draw.locked_shares;
+                }
+                line.draws.remove(0);
+            } else {
+                line.draws.set(0, draw);
+            }
+
+            if collateral_short {
+                log!(&env, "Collateral short, {} of the minimum payment left", remaining);
+                break;
+            }
+        }
+
+        let collected = line.statement.minimum_due - remaining;
+
+        if collected == 0 {
+            log!(&env, "Error: Insufficient funds for credit payment {}", remaining);
+            return Err(ContractError::InsufficientFunds);
+        }
+
+        // Whatever could not be collected rolls into the next statement
+        line.statement.minimum_due = remaining;
+
+        Self::write_persistent(&env, &DataKey::CreditLine(user.clone()), &line);
+
+        env.events().publish((
+            symbol_short!("cl_paid"),
+            user,
+            collected,
+            line.outstanding,
+        ), ());
+
+        Ok(collected)
+    }
+
+    /// Create a recurring subscription to a merchant
+    ///
+    /// Charges `amount` every `period_secs` from available Buffer shares,
+    /// without locking collateral. The first charge is due immediately.
+    pub fn create_subscription(
+        env: Env,
+        user: Address,               // Subscriber
+        merchant: Address,           // Merchant receiving the charges
+        amount: i128,                // Amount per period
+        period_secs: u64,            // Time between charges
+        cap: Option<i128>,           // Optional maximum total to charge
+    ) -> Result<String, ContractError> {
+        Self::extend_instance(&env);
+
+        user.require_auth();
+
+        if amount <= 0 || period_secs == 0 {
+            log!(&env, "Error: Invalid subscription {} every {}s", amount, period_secs);
+            return Err(ContractError::InvalidAmount);
+        }
+
+        if let Some(cap) = cap {
+            if cap < amount {
+                log!(&env, "Error: Cap {} below one charge {}", cap, amount);
+                return Err(ContractError::InvalidAmount);
+            }
+        }
+
+        let counter: u64 = env.storage()
+            .instance()
+            .get(&DataKey::SubscriptionCounter)
+            .unwrap_or(0);
+
+        let subscription_id = Self::format_id(&env, SUBSCRIPTION_ID_PREFIX, counter + 1);
+
+        env.storage()
+            .instance()
+            .set(&DataKey::SubscriptionCounter, &(counter + 1));
+
+        let current_time = env.ledger().timestamp();
+
+        let subscription = Subscription {
+            subscription_id: subscription_id.clone(),
+            user: user.clone(),
+            merchant: merchant.clone(),
+            amount,
+            period_secs,
+            cap,
+            charged_total: 0,
+            charges_count: 0,
+            next_charge_at: current_time,
+            status: SubscriptionStatus::Active,
+            created_at: current_time,
+        };
+
+        Self::write_persistent(&env, &DataKey::Subscription(subscription_id.clone()), &subscription);
+
+        env.events().publish((
+            symbol_short!("sub_new"),
+            subscription_id.clone(),
+            user,
+            merchant,
+            amount,
+            period_secs,
+        ), ());
+
+        Ok(subscription_id)
+    }
+
+    /// Collect the current subscription charge (called by automatic worker)
+    ///
+    /// Debits available shares only. Pays the same keeper bounty and stores
+    /// the same `PaymentReceipt` as plan installments, keyed by subscription
+    /// ID and charge number, and emits `sub_paid`.
+    pub fn collect_subscription(
+        env: Env,
+        subscription_id: String,     // Subscription ID
+        keeper: Address,             // Caller receiving the keeper bounty
+    ) -> Result<PaymentReceipt, ContractError> {
+        Self::extend_instance(&env);
+
+        // Permissionless: the user agreed to the amount, period and cap
+        // when creating the subscription, so any keeper can trigger a due charge
+        let mut subscription = Self::get_subscription(env.clone(), subscription_id.clone())?;
+
+        keeper.require_auth();
+
+        if subscription.status != SubscriptionStatus::Active {
+            log!(&env, "Error: Subscription not active");
+            return Err(ContractError::SubscriptionNotActive);
+        }
+
+        let current_time = env.ledger().timestamp();
+
+        if current_time < subscription.next_charge_at {
+            log!(&env, "Error: Charge not yet due {}", subscription.next_charge_at);
+            return Err(ContractError::NotDueYet);
+        }
+
+        let charged_total = subscription.charged_total.checked_add(subscription.amount)
+            .ok_or(ContractError::InvalidAmount)?;
+
+        if let Some(cap) = subscription.cap {
+            if charged_total > cap {
+                log!(&env, "Error: Subscription cap {} reached", cap);
+                return Err(ContractError::SubscriptionCapReached);
+            }
+        }
+
+        let buffer_client = Self::buffer_client(&env)?;
+        let shares_needed = buffer_client.shares_for_amount(&subscription.amount);
+        let balance = buffer_client.get_balance(&subscription.user);
+
+        if Self::debitable_shares(&env, &buffer_client, &balance, &subscription.user) < shares_needed {
+            log!(&env, "Error: Insufficient available shares for subscription");
+            return Err(ContractError::InsufficientFunds);
+        }
+
+        let result = buffer_client.debit_available(
+            &env.current_contract_address(),
+            &subscription.user,
+            &shares_needed,
+            &subscription.merchant,
+        );
+
+        let fee_amount = Self::pay_keeper_bounty(&env, &subscription_id, &keeper, subscription.amount);
+
+        let mut merchant_amount: i128 = 0;
+        for amount in result.amounts_received.iter() {
+            merchant_amount += amount;
+        }
+
+        subscription.charges_count += 1;
+        subscription.charged_total = charged_total;
+        subscription.next_charge_at += subscription.period_secs;
+
+        // Stop once the cap cannot cover another charge
+        if let Some(cap) = subscription.cap {
+            if subscription.charged_total + subscription.amount > cap {
+                subscription.status = SubscriptionStatus::Cancelled;
+            }
+        }
+
+        let receipt = PaymentReceipt {
+            installment_number: subscription.charges_count,
+            source: PaymentSource::Available,
+            shares_burned: result.shares_burned,
+            amounts_received: result.amounts_received,
+            merchant_amount,
+            fee_amount,
+            paid_at: current_time,
+        };
+
+        Self::write_persistent(&env, &DataKey::Receipt(subscription_id.clone(), subscription.charges_count), &receipt);
+
+        Self::write_persistent(&env, &DataKey::Subscription(subscription_id.clone()), &subscription);
+
+        env.events().publish((
+            symbol_short!("sub_paid"),
+            subscription_id,
+            subscription.charges_count,
+            receipt.shares_burned,
+            merchant_amount,
+        ), ());
+
+        Ok(receipt)
+    }
+
+    /// Cancel a subscription (user, any time)
+    pub fn cancel_subscription(env: Env, subscription_id: String) -> Result<(), ContractError> {
+        Self::extend_instance(&env);
+        let mut subscription = Self::get_subscription(env.clone(), subscription_id.clone())?;
+
+        subscription.user.require_auth();
+
+        if subscription.status != SubscriptionStatus::Active {
+            log!(&env, "Error: Subscription not active");
+            return Err(ContractError::SubscriptionNotActive);
+        }
+
+        subscription.status = SubscriptionStatus::Cancelled;
+
+        Self::write_persistent(&env, &DataKey::Subscription(subscription_id.clone()), &subscription);
+
+        env.events().publish((
+            symbol_short!("sub_cncl"),
+            subscription_id,
+            subscription.user,
+            subscription.merchant,
+        ), ());
+
+        Ok(())
+    }
+
+    /// Query a subscription by its ID
+    pub fn get_subscription(env: Env, subscription_id: String) -> Result<Subscription, ContractError> {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::Subscription(subscription_id))
+            .ok_or(ContractError::SubscriptionNotFound)
+    }
+
+    /// Get the next due installment of a plan
+    /// 
+    /// Searches for the first installment that is pending and already due.
+    /// Useful for automatic workers that process collections.
+    pub fn get_next_due(env: Env, plan_id: String) -> Result<Option<Installment>, ContractError> {
+        Self::extend_instance(&env);
+        let plan: BridgePlan = Self::read_persistent(&env, &DataKey::Plan(plan_id))
+            .ok_or(ContractError::PlanNotFound)?;
+        
+        // Frozen plans have nothing to collect
+        if plan.status == PlanStatus::InDispute || plan.status == PlanStatus::Cancelled {
+            return Ok(None);
+        }
+        
+        let current_time = env.ledger().timestamp();
+        
+        if current_time < plan.forbearance_until {
+            return Ok(None);
+        }
+        
+        // Search for first pending and due installment
+        for i in 0..plan.installments.len() {
+            let installment = plan.installments.get(i).unwrap();
+            if installment.status == InstallmentStatus::Pending 
+                && installment.due_date <= current_time {
+                return Ok(Some(installment));
+            }
+        }
+        
+        // No due installments
+        Ok(None)
+    }
+    
+    /// Get complete plan summary with updated Buffer values
+    /// 
+    /// Returns the plan along with the current token values of the user's Buffer
+    /// (available and protected). Useful for displaying in UI.
+    pub fn get_plan_summary(
+        env: Env, 
+        plan_id: String, 
+    ) -> Result<(BridgePlan, i128, i128), ContractError> {
+        Self::extend_instance(&env);
+        let plan = Self::get_plan(env.clone(), plan_id)?;
+        
+        let buffer_client = Self::buffer_client(&env)?;
+        let (available_value, protected_value, _total_value) = buffer_client.get_values(&plan.user);
+        
+        // Returns: (plan, available_value, protected_value)
+        Ok((plan, available_value, protected_value))
+    }
+
+    fn default_payment_preference(env: &Env) -> Vec<PaymentSource> {
+        Vec::from_array(env, [
+            PaymentSource::Wallet,
+            PaymentSource::Available,
+            PaymentSource::Protected,
+        ])
+    }
+
+    /// Unlock the collateral backing a payment made outside of it, in
+    /// proportion to the plan's unpaid balance (user and guarantor liens)
+    fn release_plan_shares(
+        env: &Env,
+        plan: &mut BridgePlan,
+        buffer_client: &BufferContractClient,
+        amount_paid: i128,
+    ) {
+        let mut unpaid_balance: i128 = 0;
+        for installment in plan.installments.iter() {
+            if installment.status != InstallmentStatus::Paid
+                && installment.status != InstallmentStatus::Waived {
+                unpaid_balance += installment.amount;
+            }
+        }
+        
+        if unpaid_balance <= 0 {
+            return;
+        }
+        
+        let amount_paid = amount_paid.min(unpaid_balance);
+        
+        let shares_to_unlock = plan.protected_shares
+            .checked_mul(amount_paid)
+            .unwrap_or(0)
+            / unpaid_balance;
+        
+        if shares_to_unlock > 0 {
+            buffer_client.unlock_shares(&plan.user, &plan.lien_id, &shares_to_unlock);
+            plan.protected_shares -= shares_to_unlock;
+        }
+        
+        let guarantor_to_unlock = plan.guarantor_shares
+            .checked_mul(amount_paid)
+            .unwrap_or(0)
+            / unpaid_balance;
+        
+        if let Some(guarantor) = &plan.guarantor {
+            if guarantor_to_unlock > 0 {
+                buffer_client.unlock_shares(guarantor, &plan.guarantor_lien_id, &guarantor_to_unlock);
+                plan.guarantor_shares -= guarantor_to_unlock;
+            }
+        }
+        
+        log!(env, "Released {} shares and {} guarantor shares", shares_to_unlock, guarantor_to_unlock);
+    }
+
+    /// Divide a payment among the plan's payees by their bps share.
+    /// The last payee takes the rounding remainder so the parts add up
+    /// exactly; plans without payees pay `default_payee` in full.
+    fn split_to_payees(
+        env: &Env,
+        plan: &BridgePlan,
+        total: i128,
+        default_payee: &Address,
+    ) -> Vec<(Address, i128)> {
+        let mut parts: Vec<(Address, i128)> = Vec::new(env);
+        
+        // Payments held in escrow are split when they are released
+        if plan.payees.is_empty() || *default_payee == env.current_contract_address() {
+            parts.push_back((default_payee.clone(), total));
+            return parts;
+        }
+        
+        let mut remaining = total;
+        let last = plan.payees.len() - 1;
+        
+        for (i, payee) in plan.payees.iter().enumerate() {
+            let part = if i as u32 == last {
+                remaining
+            } else {
+                total * payee.bps as i128 / 10000
+            };
+            remaining -= part;
+            parts.push_back((payee.payee, part));
+        }
+        
+        parts
+    }
+
+    /// Available shares the bridge may debit: the user's available balance,
+    /// capped by the allowance the user granted the bridge in the buffer
+    fn debitable_shares(
+        env: &Env,
+        buffer_client: &BufferContractClient,
+        balance: &BufferBalance,
+        user: &Address,
+    ) -> i128 {
+        balance.available_shares.min(buffer_client.allowance(user, &env.current_contract_address()))
+    }
+
+    /// Debit shares from a buffer balance (user or guarantor) to every payee,
+    /// from the given lien or from available shares when `lien_id` is None.
+    /// Returns the shares burned and the amounts received per asset.
+    fn debit_to_payees(
+        env: &Env,
+        buffer_client: &BufferContractClient,
+        plan: &BridgePlan,
+        from: &Address,
+        shares: i128,
+        default_payee: &Address,
+        lien_id: Option<u64>,
+    ) -> (i128, Vec<i128>) {
+        let mut shares_burned: i128 = 0;
+        let mut amounts_received: Vec<i128> = Vec::new(env);
+        
+        for (payee, part) in Self::split_to_payees(env, plan, shares, default_payee).iter() {
+            if part == 0 {
+                continue;
+            }
+            
+            let result = match lien_id {
+                Some(lien_id) => buffer_client.debit_protected(from, &lien_id, &part, &payee),
+                None => buffer_client.debit_available(&env.current_contract_address(), from, &part, &payee),
+            };
+            
+            shares_burned += result.shares_burned;
+            Self::add_amounts(&mut amounts_received, &result.amounts_received);
+        }
+        
+        (shares_burned, amounts_received)
+    }
+
+    /// Add per-asset amounts into a running total
+    fn add_amounts(total: &mut Vec<i128>, amounts: &Vec<i128>) {
+        for (j, amount) in amounts.iter().enumerate() {
+            let j = j as u32;
+            if j < total.len() {
+                total.set(j, total.get(j).unwrap() + amount);
+            } else {
+                total.push_back(amount);
+            }
+        }
+    }
+
+    /// Client for the configured Buffer Contract
+    fn buffer_client(env: &Env) -> Result<BufferContractClient<'_>, ContractError> {
+        let buffer_contract: Address = env.storage()
+            .instance()
+            .get(&DataKey::BufferContract)
+            .ok_or(ContractError::NoBufferContract)?;
+        
+        Ok(BufferContractClient::new(env, &buffer_contract))
+    }
+
+    /// Check metadata field lengths and that the order reference is still
+    /// free for the merchant
+    /// Drop the order ref reservation of an invoice that is no longer open
+    fn release_invoice_order_ref(env: &Env, invoice: &Invoice) {
+        if let Some(order_ref) = &invoice.metadata.order_ref {
+            env.storage()
+                .persistent()
+                .remove(&DataKey::InvoiceOrderRef(invoice.merchant.clone(), order_ref.clone()));
+        }
+    }
+
+    fn validate_metadata(env: &Env, merchant: &Address, metadata: &PlanMetadata) -> Result<(), ContractError> {
+        if let Some(currency_code) = &metadata.currency_code {
+            if currency_code.is_empty() || currency_code.len() > MAX_CURRENCY_CODE_LEN {
+                log!(env, "Error: Invalid currency code length {}", currency_code.len());
+                return Err(ContractError::InvalidMetadata);
+            }
+        }
+        
+        if let Some(order_ref) = &metadata.order_ref {
+            if order_ref.is_empty() || order_ref.len() > MAX_ORDER_REF_LEN {
+                log!(env, "Error: Invalid order reference length {}", order_ref.len());
+                return Err(ContractError::InvalidOrderRef);
+            }
+            
+            if Self::extend_persistent(env, &DataKey::OrderRef(merchant.clone(), order_ref.clone())) {
+                log!(env, "Error: Order reference already used");
+                return Err(ContractError::OrderRefTaken);
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// Pay `amount` to the plan's payees from the first source in the plan's
+    /// payment preference that can cover it. Returns the source used, the
+    /// shares burned and the amounts received, or None if no source could pay.
+    fn pay_from_sources(
+        env: &Env,
+        plan: &mut BridgePlan,
+        buffer_client: &BufferContractClient,
+        amount: i128,
+        merchant: &Address,
+    ) -> Option<(PaymentSource, i128, Vec<i128>)> {
+        
+        Self::sync_plan_collateral(env, plan, buffer_client);
+        
+        let shares_needed = buffer_client.shares_for_amount(&amount);
+        let balance = buffer_client.get_balance(&plan.user);
+        
+        let payment_token: Option<Address> = env.storage()
+            .instance()
+            .get(&DataKey::PaymentToken);
+        
+        // (source, shares burned, amounts received by the merchant)
+        let mut paid_by: Option<(PaymentSource, i128, Vec<i128>)> = None;
+        
+        for source in plan.payment_preference.iter() {
+            
+            match source {
+                
+                PaymentSource::Wallet => {
+                    
+                    // Pull tokens through the user's allowance to the bridge
+                    let token = match &payment_token {
+                        Some(token) => token,
+                        None => continue,
+                    };
+                    
+                    let token_client = TokenClient::new(env, token);
+                    let spender = env.current_contract_address();
+                    
+                    if token_client.allowance(&plan.user, &spender) < amount
+                        || token_client.balance(&plan.user) < amount {
+                        continue;
+                    }
+                    
+                    for (payee, part) in Self::split_to_payees(env, plan, amount, merchant).iter() {
+                        if part > 0 {
+                            token_client.transfer_from(&spender, &plan.user, &payee, &part);
+                        }
+                    }
+                    
+                    // Collateral backing this installment is no longer needed
+                    Self::release_plan_shares(env, plan, buffer_client, amount);
+                    
+                    log!(env, "Collected from Wallet: {} tokens", amount);
+                    paid_by = Some((source, 0, Vec::from_array(env, [amount])));
+                    break;
+                }
+                
+                PaymentSource::Available => {
+                    
+                    if Self::debitable_shares(env, buffer_client, &balance, &plan.user) < shares_needed {
+                        continue;
+                    }
+                    
+                    let (shares_burned, amounts_received) = Self::debit_to_payees(
+                        env, buffer_client, plan, &plan.user, shares_needed, merchant, None,
+                    );
+                    
+                    // Unlock the collateral backing this installment
+                    Self::release_plan_shares(env, plan, buffer_client, amount);
+                    
+                    log!(env, "Collected from Available: {} shares", shares_needed);
+                    paid_by = Some((source, shares_burned, amounts_received));
+                    break;
+                }
+                
+                PaymentSource::Protected => {
+                    
+                    // Only this plan's lien can be debited
+                    let user_protected = balance.protected_shares.min(plan.protected_shares).max(0);
+                    
+                    // The guarantor only covers what the user's protected shares cannot
+                    let user_part = if user_protected >= shares_needed {
+                        shares_needed
+                    } else if plan.guarantor.is_some() {
+                        user_protected
+                    } else {
+                        continue;
+                    };
+                    
+                    let guarantor_part = shares_needed - user_part;
+                    
+                    if guarantor_part > 0 {
+                        let guarantor = plan.guarantor.clone().unwrap();
+                        let guarantor_balance = buffer_client.get_balance(&guarantor);
+                        
+                        if plan.guarantor_shares < guarantor_part
+                            || guarantor_balance.protected_shares < guarantor_part {
+                            continue;
+                        }
+                    }
+                    
+                    let mut shares_burned: i128 = 0;
+                    let mut amounts_received: Vec<i128> = Vec::new(env);
+                    
+                    if user_part > 0 {
+                        let (burned, received) = Self::debit_to_payees(
+                            env, buffer_client, plan, &plan.user, user_part, merchant, Some(plan.lien_id),
+                        );
+                        shares_burned += burned;
+                        Self::add_amounts(&mut amounts_received, &received);
+                        
+                        // Reduce plan's protected shares
+                        plan.protected_shares = plan.protected_shares.checked_sub(user_part)
+                            .unwrap_or_else(|| {
+                                log!(env, "Error: Shares protegidos insuficientes");
+                                0
+                            });
+                    }
+                    
+                    if guarantor_part > 0 {
+                        let guarantor = plan.guarantor.clone().unwrap();
+                        let (burned, received) = Self::debit_to_payees(
+                            env, buffer_client, plan, &guarantor, guarantor_part, merchant,
+                            Some(plan.guarantor_lien_id),
+                        );
+                        shares_burned += burned;
+                        Self::add_amounts(&mut amounts_received, &received);
+                        plan.guarantor_shares -= guarantor_part;
+                        
+                        log!(env, "Guarantor covered {} shares", guarantor_part);
+                    }
+                    
+                    log!(env, "Collected from Protected: {} shares", shares_needed);
+                    paid_by = Some((source, shares_burned, amounts_received));
+                    break;
+                }
+            }
+        }
+        
+        paid_by
+    }
+
+    /// Latest due date in the plan's current schedule
+    fn last_due_date(plan: &BridgePlan) -> u64 {
+        let mut last = 0;
+        for installment in plan.installments.iter() {
+            last = last.max(installment.due_date);
+        }
+        last
+    }
+
+    /// Lien expiry covering a schedule that ends at `last_due`.
+    /// Rejects schedules the Buffer Contract could not lock for.
+    fn lien_expiry(env: &Env, last_due: u64) -> Result<u64, ContractError> {
+        let expires_at = last_due.checked_add(LIEN_GRACE_SECS)
+            .ok_or(ContractError::InvalidDueDate)?;
+
+        if expires_at > env.ledger().timestamp() + MAX_LIEN_DURATION_SECS {
+            log!(env, "Error: Lien expiry {} beyond the buffer maximum", expires_at);
+            return Err(ContractError::InvalidDueDate);
+        }
+
+        Ok(expires_at)
+    }
+
+    /// Push the expiry of the plan's liens (user and guarantor) forward.
+    /// Expiries never move backwards.
+    fn extend_plan_liens(
+        env: &Env,
+        plan: &mut BridgePlan,
+        buffer_client: &BufferContractClient,
+        expires_at: u64,
+    ) {
+        if expires_at <= plan.lien_expires_at {
+            return;
+        }
+        
+        Self::sync_plan_collateral(env, plan, buffer_client);
+        
+        if plan.protected_shares > 0 {
+            buffer_client.extend_lien(&plan.user, &plan.lien_id, &expires_at);
+        }
+        
+        if let Some(guarantor) = &plan.guarantor {
+            if plan.guarantor_shares > 0 {
+                buffer_client.extend_lien(guarantor, &plan.guarantor_lien_id, &expires_at);
+            }
+        }
+        
+        plan.lien_expires_at = expires_at;
+        log!(env, "Plan liens extended to {}", expires_at);
+    }
+
+    /// Shares a lien still holds for the bridge. A lien the user reclaimed,
+    /// or one past its expiry that the user may reclaim at any moment,
+    /// counts as no collateral.
+    fn lien_shares(env: &Env, buffer_client: &BufferContractClient, lien_id: u64) -> i128 {
+        if lien_id == 0 {
+            return 0;
+        }
+        
+        match buffer_client.find_lien(&lien_id) {
+            Some(lien) if lien.owner == env.current_contract_address()
+                && env.ledger().timestamp() <= lien.expires_at => lien.shares,
+            _ => 0,
+        }
+    }
+
+    /// Cap the plan's collateral (user and guarantor) at what its liens
+    /// still hold, so no unlock or debit reaches for reclaimed shares
+    fn sync_plan_collateral(env: &Env, plan: &mut BridgePlan, buffer_client: &BufferContractClient) {
+        let held = Self::lien_shares(env, buffer_client, plan.lien_id);
+        if plan.protected_shares > held {
+            log!(env, "Plan lien holds {} of {} shares", held, plan.protected_shares);
+            plan.protected_shares = held;
+        }
+        
+        let guarantor_held = Self::lien_shares(env, buffer_client, plan.guarantor_lien_id);
+        if plan.guarantor_shares > guarantor_held {
+            log!(env, "Guarantor lien holds {} of {} shares", guarantor_held, plan.guarantor_shares);
+            plan.guarantor_shares = guarantor_held;
+        }
+    }
+
+    /// Unlock whatever is left of the guarantor's collateral
+    fn release_guarantor(env: &Env, plan: &mut BridgePlan, buffer_client: &BufferContractClient) {
+        if let Some(guarantor) = &plan.guarantor {
+            if plan.guarantor_shares > 0 {
+                buffer_client.unlock_shares(guarantor, &plan.guarantor_lien_id, &plan.guarantor_shares);
+                log!(env, "Released {} guarantor shares", plan.guarantor_shares);
+                plan.guarantor_shares = 0;
+            }
+        }
+    }
+
+    /// Pay the keeper bounty for a collected installment out of the pool.
+    /// Skipped when no bounty is configured or the pool cannot cover it.
+    fn pay_keeper_bounty(env: &Env, plan_id: &String, keeper: &Address, installment_amount: i128) -> i128 {
+        let config = Self::get_config(env.clone());
+        let bounty = installment_amount * config.keeper_bounty_bps as i128 / 10000
+            + config.keeper_bounty_flat;
+        
+        if bounty <= 0 {
+            return 0;
+        }
+        
+        let token: Option<Address> = env.storage().instance().get(&DataKey::PaymentToken);
+        let pool = Self::get_bounty_pool(env.clone());
+        
+        let token = match token {
+            Some(token) if pool >= bounty => token,
+            _ => {
+                log!(env, "Bounty pool cannot cover {}", bounty);
+                return 0;
+            }
+        };
+        
+        TokenClient::new(env, &token).transfer(&env.current_contract_address(), keeper, &bounty);
+        env.storage().instance().set(&DataKey::BountyPool, &(pool - bounty));
+        
+        env.events().publish((
+            symbol_short!("bounty"),
+            plan_id.clone(),
+            keeper.clone(),
+            bounty,
+        ), ());
+        
+        bounty
+    }
+
+    /// Mark the plan completed once every installment is paid or waived,
+    /// releasing the remaining collateral
+    fn complete_if_settled(env: &Env, plan: &mut BridgePlan, buffer_client: &BufferContractClient) {
+        let all_paid = (0..plan.installments.len()).all(|i| {
+            let status = plan.installments.get(i).unwrap().status;
+            status == InstallmentStatus::Paid || status == InstallmentStatus::Waived
+        });
+        
+        if all_paid {
+            plan.status = PlanStatus::Completed;
+            Self::sync_plan_collateral(env, plan, buffer_client);
+            
+            // Release remaining protected shares (if any)
+            if plan.protected_shares > 0 {
+                buffer_client.unlock_shares(&plan.user, &plan.lien_id, &plan.protected_shares);
+                log!(env, "Released {} remaining shares", plan.protected_shares);
+                plan.protected_shares = 0;
+            }
+            
+            Self::release_guarantor(env, plan, buffer_client);
+        }
+    }
+
+    /// Waive the installments held in escrow and send the held tokens back
+    /// to the buyer. Returns the amount refunded.
+    fn refund_escrow(
+        env: &Env,
+        plan_id: &String,
+        plan: &mut BridgePlan,
+    ) -> Result<i128, ContractError> {
+        let mut escrow: Escrow = match Self::read_persistent(env, &DataKey::Escrow(plan_id.clone())) {
+            Some(escrow) => escrow,
+            None => return Ok(0),
+        };
+        
+        if escrow.held_amount == 0 {
+            return Ok(0);
+        }
+        
+        let mut waived_amount: i128 = 0;
+        for i in 0..plan.installments.len() {
+            let mut installment = plan.installments.get(i).unwrap();
+            if installment.status == InstallmentStatus::Held {
+                waived_amount += installment.amount;
+                installment.status = InstallmentStatus::Waived;
+                plan.installments.set(i, installment);
+            }
+        }
+        
+        // Paid back to the buyer's wallet: the bridge cannot deposit into
+        // someone else's Buffer
+        let refunded = escrow.held_amount;
+        TokenClient::new(env, &Self::get_payment_token(env)?)
+            .transfer(&env.current_contract_address(), &plan.user, &refunded);
+        
+        plan.total_amount -= waived_amount;
+        escrow.held_amount = 0;
+        Self::write_persistent(env, &DataKey::Escrow(plan_id.clone()), &escrow);
+        
+        env.events().publish((
+            symbol_short!("esc_rfnd"),
+            plan_id.clone(),
+            plan.user.clone(),
+            refunded,
+        ), ());
+        
+        Ok(refunded)
+    }
+
+    /// Build a printable ID: prefix plus the sequence number
+    /// zero-padded to at least `PLAN_ID_MIN_DIGITS` digits
+    fn format_id(env: &Env, prefix: &[u8; 4], sequence: u64) -> String {
+        // Decimal digits, least significant first
+        let mut digits = [0u8; 20];
+        let mut len = 0;
+        let mut n = sequence;
+        loop {
+            digits[len] = b'0' + (n % 10) as u8;
+            n /= 10;
+            len += 1;
+            if n == 0 {
+                break;
+            }
+        }
+        
+        let width = len.max(PLAN_ID_MIN_DIGITS);
+        let prefix_len = prefix.len();
+        let mut id_bytes = [b'0'; 24];
+        id_bytes[..prefix_len].copy_from_slice(prefix);
+        
+        for i in 0..len {
+            id_bytes[prefix_len + width - 1 - i] = digits[i];
+        }
+        
+        String::from_bytes(env, &id_bytes[..prefix_len + width])
+    }
+
+    fn extend_instance(env: &Env) {
+        env.storage()
+            .instance()
+            .extend_ttl(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+    }
+
+    /// Extend a persistent entry if it exists, so it is never archived while
+    /// in use. Returns whether the entry exists.
+    fn extend_persistent(env: &Env, key: &DataKey) -> bool {
+        let exists = env.storage().persistent().has(key);
+        if exists {
+            env.storage()
+                .persistent()
+                .extend_ttl(key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+        }
+        exists
+    }
+
+    /// Read a persistent entry, bumping its TTL
+    fn read_persistent<V: TryFromVal<Env, Val>>(env: &Env, key: &DataKey) -> Option<V> {
+        if Self::extend_persistent(env, key) {
+            env.storage().persistent().get(key)
+        } else {
+            None
+        }
+    }
+
+    /// Write a persistent entry, bumping its TTL
+    fn write_persistent<V: IntoVal<Env, Val>>(env: &Env, key: &DataKey, value: &V) {
+        env.storage().persistent().set(key, value);
+        Self::extend_persistent(env, key);
+    }
+
+    fn get_payment_token(env: &Env) -> Result<Address, ContractError> {
+        env.storage()
+            .instance()
+            .get(&DataKey::PaymentToken)
+            .ok_or(ContractError::NoPaymentToken)
+    }
+
+    fn get_admin(env: &Env) -> Address {
+        env.storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .unwrap_or_else(|| panic!("Admin not set"))
+    }
+}
+
+// ============ TESTS WITH MOCK BUFFER ============
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soroban_sdk::{testutils::Address as _, testutils::Ledger, testutils::storage::{Instance as _, Persistent as _}, token::StellarAssetClient, Bytes, Env, Vec as SorobanVec};
+    
+    // Simple MOCK returning fixed balances
+    #[contract]
+    pub struct MockBuffer;
+
+    #[contractimpl]
+    impl MockBuffer {
+        pub fn get_balance(env: Env, user: Address) -> BufferBalance {
+            let (available_shares, protected_shares) = env.storage()
+                .instance()
+                .get(&(symbol_short!("balance"), user))
+                .unwrap_or((10000, 0));
+
+            BufferBalance {
+                available_shares,
+                protected_shares,
+                total_deposited: 10000,
+                last_deposit_ts: 0,
+                version: 1,
+            }
+        }
+
+        /// Override the shares reported by `get_balance`
+        pub fn set_balance(env: Env, user: Address, available: i128, protected: i128) {
+            env.storage().instance().set(&(symbol_short!("balance"), user), &(available, protected));
+        }
+
+        pub fn lock_shares(env: Env, consumer: Address, user: Address, shares: i128, expires_at: Option<u64>) -> LockResult {
+            let lien_id: u64 = env.storage().instance().get(&symbol_short!("lien_ctr")).unwrap_or(0) + 1;
+            env.storage().instance().set(&symbol_short!("lien_ctr"), &lien_id);
+            env.storage().instance().set(&(symbol_short!("lien"), lien_id), &Lien {
+                id: lien_id,
+                user: user.clone(),
+                owner: consumer,
+                shares,
+                expires_at: expires_at.unwrap_or(u64::MAX),
+                created_at: env.ledger().timestamp(),
+            });
+
+            let mut liens: SorobanVec<u64> = env.storage().instance()
+                .get(&(symbol_short!("liens"), user.clone()))
+                .unwrap_or(SorobanVec::new(&env));
+            liens.push_back(lien_id);
+            env.storage().instance().set(&(symbol_short!("liens"), user), &liens);
+
+            LockResult { lien_id, shares_locked: shares, new_available: 10000 - shares, new_protected: shares }
+        }
+
+        pub fn unlock_shares(env: Env, _user: Address, lien_id: u64, shares: i128) -> LockResult {
+            Self::reduce_lien(&env, lien_id, shares);
+            LockResult { lien_id, shares_locked: shares, new_available: 10000 + shares, new_protected: 0 }
+        }
+
+        /// IDs of the user's liens that still hold shares
+        pub fn get_liens(env: Env, user: Address) -> Vec<u64> {
+            let liens: SorobanVec<u64> = env.storage().instance()
+                .get(&(symbol_short!("liens"), user))
+                .unwrap_or(SorobanVec::new(&env));
+
+            let mut open = SorobanVec::new(&env);
+            for lien_id in liens.iter() {
+                if Self::find_lien(env.clone(), lien_id).is_some_and(|lien| lien.shares > 0) {
+                    open.push_back(lien_id);
+                }
+            }
+            open
+        }
+
+        pub fn find_lien(env: Env, lien_id: u64) -> Option<Lien> {
+            env.storage().instance().get(&(symbol_short!("lien"), lien_id))
+        }
+
+        /// Drop a lien the way the buffer's `reclaim_expired_lock` does
+        pub fn reclaim_lien(env: Env, lien_id: u64) {
+            env.storage().instance().remove(&(symbol_short!("lien"), lien_id));
+        }
+
+        fn reduce_lien(env: &Env, lien_id: u64, shares: i128) {
+            let mut lien = Self::find_lien(env.clone(), lien_id).expect("Lien not found");
+            lien.shares = (lien.shares - shares).max(0);
+            env.storage().instance().set(&(symbol_short!("lien"), lien_id), &lien);
+        }
+
+        pub fn extend_lien(env: Env, _user: Address, lien_id: u64, expires_at: u64) {
+            let mut lien = Self::find_lien(env.clone(), lien_id).expect("Lien not found");
+            lien.expires_at = expires_at;
+            env.storage().instance().set(&(symbol_short!("lien"), lien_id), &lien);
+        }
+
+        pub fn approve_consumer(env: Env, user: Address, consumer: Address, shares: i128) {
+            env.storage().instance().set(&(symbol_short!("allow"), user, consumer), &shares);
+        }
+
+        pub fn allowance(env: Env, user: Address, consumer: Address) -> i128 {
+            env.storage().instance().get(&(symbol_short!("allow"), user, consumer)).unwrap_or(0)
+        }
+
+        pub fn debit_available(env: Env, consumer: Address, user: Address, shares: i128, to: Address) -> WithdrawResult {
+            let allowance = Self::allowance(env.clone(), user.clone(), consumer.clone());
+            assert!(allowance >= shares, "Insufficient allowance");
+            Self::approve_consumer(env.clone(), user, consumer, allowance - shares);
+            Self::record_debit(&env, to, shares);
+            WithdrawResult {
+                shares_burned: shares,
+                amounts_received: SorobanVec::from_array(&env, [shares]),
+                new_available_balance: 10000 - shares,
+                from_protected: false,
+            }
+        }
+
+        pub fn debit_protected(env: Env, _user: Address, lien_id: u64, shares: i128, to: Address) -> WithdrawResult {
+            Self::reduce_lien(&env, lien_id, shares);
+            Self::record_debit(&env, to, shares);
+            WithdrawResult {
+                shares_burned: shares,
+                amounts_received: SorobanVec::from_array(&env, [shares]),
+                new_available_balance: 10000,
+                from_protected: true,
+            }
+        }
+
+        /// Total shares debited to a recipient
+        pub fn debited(env: Env, to: Address) -> i128 {
+            env.storage().instance().get(&to).unwrap_or(0)
+        }
+
+        /// Pay debits out in `asset` (one token per share) from the mock's own balance
+        pub fn set_asset(env: Env, asset: Address) {
+            env.storage().instance().set(&symbol_short!("asset"), &asset);
+        }
+
+        fn record_debit(env: &Env, to: Address, shares: i128) {
+            let total: i128 = env.storage().instance().get(&to).unwrap_or(0);
+            env.storage().instance().set(&to, &(total + shares));
+
+            let asset: Option<Address> = env.storage().instance().get(&symbol_short!("asset"));
+            if let Some(asset) = asset {
+                TokenClient::new(env, &asset).transfer(&env.current_contract_address(), &to, &shares);
+            }
+        }
+
+        pub fn get_values(_env: Env, _user: Address) -> (i128, i128, i128) {
+            (10000, 0, 10000)
+        }
+
+        pub fn shares_for_amount(_env: Env, amount: i128) -> i128 {
+            amount
+        }
+    }
+
+    pub struct TestContext {
+        pub env: Env,
+        pub admin: Address,
+        pub user: Address,
+        pub merchant: Address,
+        pub buffer: Address,
+        pub bridge: Address,
+    }
+
+    impl TestContext {
+        pub fn new() -> Self {
+            let env = Env::default();
+            env.mock_all_auths();
+            env.ledger().set_timestamp(1000);
+
+            let admin = Address::generate(&env);
+            let buffer = env.register(MockBuffer, ());
+            let bridge = env.register(BridgeContract, (admin.clone(),));
+            BridgeContractClient::new(&env, &bridge).set_buffer_contract(&buffer);
+
+            // The user lets the bridge debit their whole available balance
+            let user = Address::generate(&env);
+            MockBufferClient::new(&env, &buffer).approve_consumer(&user, &bridge, &10000);
+
+            Self {
+                env: env.clone(),
+                admin,
+                user,
+                merchant: Address::generate(&env),
+                buffer,
+                bridge,
+            }
+        }
+
+        pub fn client(&self) -> BridgeContractClient<'_> {
+            BridgeContractClient::new(&self.env, &self.bridge)
+        }
+
+        /// Plan options with no metadata, guarantor or escrow
+        pub fn options(&self) -> PlanOptions {
+            PlanOptions {
+                metadata: PlanMetadata {
+                    order_ref: None,
+                    invoice_hash: None,
+                    category_code: None,
+                    currency_code: None,
+                },
+                guarantor: None,
+                delivery_deadline: None,
+            }
+        }
+
+        /// Configure a payment token that the buffer also pays debits in,
+        /// as escrow custody needs
+        pub fn payment_token(&self) -> TokenClient<'_> {
+            let token = self.env.register_stellar_asset_contract_v2(Address::generate(&self.env)).address();
+            StellarAssetClient::new(&self.env, &token).mint(&self.buffer, &10000);
+            MockBufferClient::new(&self.env, &self.buffer).set_asset(&token);
+            self.client().set_payment_token(&token);
+            TokenClient::new(&self.env, &token)
+        }
+
+        pub fn advance_time(&self, seconds: u64) {
+            self.env.ledger().set_timestamp(self.env.ledger().timestamp() + seconds);
+        }
+    }
+
+    #[test]
+    fn test_create_plan_basic() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+        let plan = client.get_plan(&plan_id);
+
+        assert_eq!(plan.user, ctx.user);
+        assert_eq!(plan.merchant, ctx.merchant);
+        assert_eq!(plan.total_amount, 3000);
+        assert_eq!(plan.installments.len(), 3);
+        assert_eq!(plan.lien_id, 1);
+        assert_eq!(plan.lien_expires_at, 4000 + LIEN_GRACE_SECS);
+    }
+
+    #[test]
+    fn test_full_lifecycle() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        ctx.advance_time(1500);
+        let receipt = client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+        assert_eq!(receipt.source, PaymentSource::Available);
+
+        ctx.advance_time(1000);
+        client.collect_installment(&plan_id, &2, &ctx.merchant, &ctx.user);
+
+        ctx.advance_time(1000);
+        client.collect_installment(&plan_id, &3, &ctx.merchant, &ctx.user);
+
+        let final_plan = client.get_plan(&plan_id);
+        assert_eq!(final_plan.status, PlanStatus::Completed);
+    }
+
+    #[test]
+    fn test_ltv_max_allowed() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        // Buffer total = 10000, LTV 80% = 8000 maximum allowed
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &8000, &due_dates, &ctx.options());
+        let plan = client.get_plan(&plan_id);
+
+        assert_eq!(plan.total_amount, 8000);
+        assert_eq!(plan.status, PlanStatus::Active);
+    }
+
+    #[test]
+    fn test_ltv_exceeds_maximum() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        // Attempt to create plan for 9000 when maximum is 8000 (80% of 10000)
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let res = client.try_create_plan(&ctx.user, &ctx.merchant, &9000, &due_dates, &ctx.options());
+        assert_eq!(res, Err(Ok(ContractError::ExceedsMaxLTV)));
+    }
+
+    #[test]
+    fn test_ltv_at_100_percent_fails() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        // Attempting to use 100% of buffer (10000) should fail
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let res = client.try_create_plan(&ctx.user, &ctx.merchant, &10000, &due_dates, &ctx.options());
+        assert_eq!(res, Err(Ok(ContractError::ExceedsMaxLTV)));
+    }
+
+    #[test]
+    fn test_restructure_plan() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        ctx.advance_time(1500);
+        client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+
+        // Stretch the remaining 2000 over four installments
+        let new_dates = SorobanVec::from_array(&ctx.env, [4000u64, 6000, 8000, 10000]);
+        let new_amounts = SorobanVec::from_array(&ctx.env, [500i128, 500, 500, 500]);
+        client.restructure_plan(&plan_id, &new_dates, &new_amounts);
+
+        let plan = client.get_plan(&plan_id);
+        assert_eq!(plan.status, PlanStatus::Restructured);
+        assert_eq!(plan.installments_count, 5);
+        assert_eq!(plan.installments.get(0).unwrap().status, InstallmentStatus::Paid);
+        assert_eq!(plan.installments.get(4).unwrap().number, 5);
+        assert_eq!(plan.schedule_history.len(), 1);
+        assert_eq!(plan.schedule_history.get(0).unwrap().installments.len(), 3);
+        assert_eq!(plan.lien_expires_at, 10000 + LIEN_GRACE_SECS);
+    }
+
+    #[test]
+    fn test_restructure_amounts_mismatch() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        let new_dates = SorobanVec::from_array(&ctx.env, [4000u64, 6000]);
+        let new_amounts = SorobanVec::from_array(&ctx.env, [1000i128, 1000]);
+        let res = client.try_restructure_plan(&plan_id, &new_dates, &new_amounts);
+        assert_eq!(res, Err(Ok(ContractError::AmountsMismatch)));
+    }
+
+    #[test]
+    fn test_restructure_rejects_bad_dates() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        ctx.advance_time(1500);
+        client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+        let amounts = SorobanVec::from_array(&ctx.env, [1000i128, 1000]);
+
+        // Out of order
+        let dates = SorobanVec::from_array(&ctx.env, [6000u64, 4000]);
+        let res = client.try_restructure_plan(&plan_id, &dates, &amounts);
+        assert_eq!(res, Err(Ok(ContractError::InvalidDueDate)));
+
+        // Beyond what the buffer can keep locked
+        let too_far = 2500 + MAX_LIEN_DURATION_SECS;
+        let dates = SorobanVec::from_array(&ctx.env, [4000u64, too_far]);
+        let res = client.try_restructure_plan(&plan_id, &dates, &amounts);
+        assert_eq!(res, Err(Ok(ContractError::InvalidDueDate)));
+    }
+
+    #[test]
+    fn test_dispute_blocks_collection() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        client.open_dispute(&plan_id, &1);
+        assert_eq!(client.get_plan(&plan_id).status, PlanStatus::InDispute);
+
+        ctx.advance_time(1500);
+        assert!(client.get_next_due(&plan_id).is_none());
+        let res = client.try_collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+        assert_eq!(res.err(), Some(Ok(ContractError::PlanInDispute)));
+    }
+
+    #[test]
+    fn test_dispute_resolutions() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        // Partial refund forgives the last installment and half its collateral
+        client.open_dispute(&plan_id, &1);
+        let released = client.resolve_dispute(&plan_id, &DisputeOutcome::PartialRefund(1000));
+        let plan = client.get_plan(&plan_id);
+        assert_eq!(released, 1000);
+        assert_eq!(plan.status, PlanStatus::Active);
+        assert_eq!(plan.total_amount, 2000);
+        assert_eq!(plan.protected_shares, 2000);
+        assert_eq!(plan.installments.get(2).unwrap().status, InstallmentStatus::Waived);
+
+        // Full cancel releases everything left
+        client.open_dispute(&plan_id, &2);
+        let released = client.resolve_dispute(&plan_id, &DisputeOutcome::FullCancel);
+        let plan = client.get_plan(&plan_id);
+        assert_eq!(released, 2000);
+        assert_eq!(plan.status, PlanStatus::Cancelled);
+        assert_eq!(plan.protected_shares, 0);
+        assert!(client.try_get_dispute(&plan_id).is_err());
+    }
+
+    #[test]
+    fn test_forbearance_pauses_collection() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        ctx.advance_time(1500);
+        let until = client.grant_forbearance(&plan_id, &ctx.merchant, &30);
+        assert_eq!(until, 2500 + 30 * 86400);
+
+        let plan = client.get_plan(&plan_id);
+        assert_eq!(plan.installments.get(0).unwrap().due_date, 2000 + 30 * 86400);
+        assert!(client.get_next_due(&plan_id).is_none());
+
+        // After the pause the shifted first installment is collectable again
+        ctx.advance_time(30 * 86400);
+        assert_eq!(client.get_next_due(&plan_id).unwrap().number, 1);
+    }
+
+    #[test]
+    fn test_forbearance_days_capped() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        let res = client.try_grant_forbearance(&plan_id, &ctx.admin, &91);
+        assert_eq!(res, Err(Ok(ContractError::ForbearanceLimitExceeded)));
+
+        // Overflowing day counts are rejected, not wrapped
+        let res = client.try_grant_forbearance(&plan_id, &ctx.admin, &u32::MAX);
+        assert_eq!(res, Err(Ok(ContractError::ForbearanceLimitExceeded)));
+    }
+
+    #[test]
+    fn test_forbearance_caps_validated() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let mut config = client.get_config();
+        config.max_forbearance_days = MAX_FORBEARANCE_DAYS + 1;
+        assert_eq!(client.try_update_config(&config), Err(Ok(ContractError::InvalidForbearanceCap)));
+
+        config.max_forbearance_days = MAX_FORBEARANCE_DAYS;
+        config.max_forbearance_count = 0;
+        assert_eq!(client.try_update_config(&config), Err(Ok(ContractError::InvalidForbearanceCap)));
+    }
+
+    #[test]
+    fn test_collect_from_wallet_first() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let token_admin = Address::generate(&ctx.env);
+        let token = ctx.env.register_stellar_asset_contract_v2(token_admin).address();
+        StellarAssetClient::new(&ctx.env, &token).mint(&ctx.user, &5000);
+        TokenClient::new(&ctx.env, &token).approve(&ctx.user, &ctx.bridge, &1000, &1000);
+        client.set_payment_token(&token);
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        ctx.advance_time(1500);
+        let receipt = client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+        assert_eq!(receipt.source, PaymentSource::Wallet);
+        assert_eq!(receipt.shares_burned, 0);
+        assert_eq!(TokenClient::new(&ctx.env, &token).balance(&ctx.merchant), 1000);
+
+        // Allowance is used up, so the buffer pays the next one
+        ctx.advance_time(1000);
+        let receipt = client.collect_installment(&plan_id, &2, &ctx.merchant, &ctx.user);
+        assert_eq!(receipt.source, PaymentSource::Available);
+    }
+
+    #[test]
+    fn test_liens_released_as_plan_is_paid() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let buffer = MockBufferClient::new(&ctx.env, &ctx.buffer);
+
+        let token_admin = Address::generate(&ctx.env);
+        let token = ctx.env.register_stellar_asset_contract_v2(token_admin).address();
+        StellarAssetClient::new(&ctx.env, &token).mint(&ctx.user, &5000);
+        TokenClient::new(&ctx.env, &token).approve(&ctx.user, &ctx.bridge, &1000, &1000);
+        client.set_payment_token(&token);
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        // Wallet payment unlocks a third of the lien
+        ctx.advance_time(1000);
+        client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+        assert_eq!(client.get_plan(&plan_id).protected_shares, 2000);
+
+        // Available payments unlock the rest
+        ctx.advance_time(1000);
+        client.collect_installment(&plan_id, &2, &ctx.merchant, &ctx.user);
+        ctx.advance_time(1000);
+        client.collect_installment(&plan_id, &3, &ctx.merchant, &ctx.user);
+
+        assert_eq!(client.get_plan(&plan_id).status, PlanStatus::Completed);
+        assert!(buffer.get_liens(&ctx.user).is_empty());
+    }
+
+    #[test]
+    fn test_plan_survives_reclaimed_lien() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let buffer = MockBufferClient::new(&ctx.env, &ctx.buffer);
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        // The lien lapses and the user takes the shares back
+        let plan = client.get_plan(&plan_id);
+        ctx.env.ledger().set_timestamp(plan.lien_expires_at + 1);
+        buffer.reclaim_lien(&plan.lien_id);
+
+        // Late payments still go through, with no collateral left to release
+        let receipt = client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+        assert_eq!(receipt.source, PaymentSource::Available);
+        assert_eq!(client.get_plan(&plan_id).protected_shares, 0);
+
+        client.open_dispute(&plan_id, &1);
+        let released = client.resolve_dispute(&plan_id, &DisputeOutcome::PartialRefund(1000));
+        assert_eq!(released, 0);
+
+        client.collect_installment(&plan_id, &2, &ctx.merchant, &ctx.user);
+        assert_eq!(client.get_plan(&plan_id).status, PlanStatus::Completed);
+    }
+
+    #[test]
+    fn test_available_needs_buffer_allowance() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let buffer = MockBufferClient::new(&ctx.env, &ctx.buffer);
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        // Without an allowance the bridge cannot touch available shares
+        buffer.approve_consumer(&ctx.user, &ctx.bridge, &0);
+        buffer.set_balance(&ctx.user, &7000, &3000);
+
+        ctx.advance_time(1000);
+        let receipt = client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+        assert_eq!(receipt.source, PaymentSource::Protected);
+    }
+
+    #[test]
+    fn test_payment_preference_requires_protected() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        let preference = SorobanVec::from_array(&ctx.env, [PaymentSource::Wallet, PaymentSource::Available]);
+        let res = client.try_set_payment_preference(&plan_id, &preference);
+        assert_eq!(res, Err(Ok(ContractError::InvalidPaymentSource)));
+    }
+
+    #[test]
+    fn test_payment_receipt_recorded() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        ctx.advance_time(1500);
+        client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+
+        let receipt = client.get_receipt(&plan_id, &1);
+        assert_eq!(receipt.installment_number, 1);
+        assert_eq!(receipt.source, PaymentSource::Available);
+        assert_eq!(receipt.shares_burned, 1000);
+        assert_eq!(receipt.merchant_amount, 1000);
+        assert_eq!(receipt.fee_amount, 0);
+        assert_eq!(receipt.paid_at, 2500);
+    }
+
+    #[test]
+    fn test_plan_ids_and_metadata() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let order_ref = String::from_str(&ctx.env, "ORDER-42");
+        let metadata = PlanMetadata {
+            order_ref: Some(order_ref.clone()),
+            invoice_hash: Some(BytesN::from_array(&ctx.env, &[7u8; 32])),
+            category_code: Some(5411),
+            currency_code: Some(String::from_str(&ctx.env, "USD")),
+        };
+        let options = PlanOptions { metadata: metadata.clone(), ..ctx.options() };
+        let first = client.create_plan(&ctx.user, &ctx.merchant, &1000, &due_dates, &options);
+        let second = client.create_plan(&ctx.user, &ctx.merchant, &1000, &due_dates, &ctx.options());
+
+        assert_eq!(first, String::from_str(&ctx.env, "PLN-000001"));
+        assert_eq!(second, String::from_str(&ctx.env, "PLN-000002"));
+
+        let plan = client.get_plan_by_order_ref(&ctx.merchant, &order_ref);
+        assert_eq!(plan.plan_id, first);
+        assert_eq!(plan.metadata, metadata);
+    }
+
+    #[test]
+    fn test_order_ref_unique_per_merchant() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let mut options = ctx.options();
+        options.metadata.order_ref = Some(String::from_str(&ctx.env, "ORDER-42"));
+        client.create_plan(&ctx.user, &ctx.merchant, &1000, &due_dates, &options);
+        let res = client.try_create_plan(&ctx.user, &ctx.merchant, &1000, &due_dates, &options);
+        assert_eq!(res, Err(Ok(ContractError::OrderRefTaken)));
+    }
+
+    #[test]
+    fn test_invoice_accept_creates_plan() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let templates = SorobanVec::from_array(&ctx.env, [
+            InstallmentTemplate { installments_count: 3, interval_secs: 1000 },
+            InstallmentTemplate { installments_count: 6, interval_secs: 500 },
+        ]);
+        let invoice_id = client.create_invoice(&ctx.merchant, &3000, &templates, &5000, &None);
+        assert_eq!(invoice_id, String::from_str(&ctx.env, "INV-000001"));
+
+        let plan_id = client.accept_invoice(&invoice_id, &ctx.user, &1);
+        let plan = client.get_plan(&plan_id);
+        assert_eq!(plan.merchant, ctx.merchant);
+        assert_eq!(plan.total_amount, 3000);
+        assert_eq!(plan.installments_count, 6);
+        assert_eq!(plan.installments.get(0).unwrap().due_date, 1500);
+        assert_eq!(plan.auth_path, AuthPath::Invoice);
+
+        let invoice = client.get_invoice(&invoice_id);
+        assert_eq!(invoice.status, InvoiceStatus::Accepted);
+        assert_eq!(invoice.plan_id, Some(plan_id));
+
+        // Single use
+        assert!(client.try_accept_invoice(&invoice_id, &ctx.user, &0).is_err());
+    }
+
+    #[test]
+    fn test_invoice_expired() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let templates = SorobanVec::from_array(&ctx.env, [
+            InstallmentTemplate { installments_count: 3, interval_secs: 1000 },
+        ]);
+        let invoice_id = client.create_invoice(&ctx.merchant, &3000, &templates, &2000, &None);
+
+        ctx.advance_time(1000);
+        let res = client.try_accept_invoice(&invoice_id, &ctx.user, &0);
+        assert_eq!(res, Err(Ok(ContractError::InvoiceExpired)));
+    }
+
+    #[test]
+    fn test_invoice_reserves_order_ref() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let templates = SorobanVec::from_array(&ctx.env, [
+            InstallmentTemplate { installments_count: 3, interval_secs: 1000 },
+        ]);
+        let metadata = Some(PlanMetadata {
+            order_ref: Some(String::from_str(&ctx.env, "ORDER-7")),
+            invoice_hash: None,
+            category_code: None,
+            currency_code: None,
+        });
+        let first = client.create_invoice(&ctx.merchant, &3000, &templates, &5000, &metadata);
+        let res = client.try_create_invoice(&ctx.merchant, &3000, &templates, &5000, &metadata);
+        assert_eq!(res, Err(Ok(ContractError::OrderRefTaken)));
+
+        // Cancelling frees the ref for a new invoice
+        client.cancel_invoice(&first);
+        let second = client.create_invoice(&ctx.merchant, &3000, &templates, &2000, &metadata);
+
+        // So does expiry
+        ctx.advance_time(1000);
+        let third = client.create_invoice(&ctx.merchant, &3000, &templates, &5000, &metadata);
+        assert_ne!(second, third);
+        client.accept_invoice(&third, &ctx.user, &0);
+    }
+
+    #[test]
+    fn test_invoice_validated_at_creation() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let templates = SorobanVec::from_array(&ctx.env, [
+            InstallmentTemplate { installments_count: 3, interval_secs: 1000 },
+        ]);
+        let metadata = Some(PlanMetadata {
+            order_ref: Some(String::from_str(&ctx.env, "")),
+            invoice_hash: None,
+            category_code: None,
+            currency_code: None,
+        });
+        let res = client.try_create_invoice(&ctx.merchant, &3000, &templates, &5000, &metadata);
+        assert_eq!(res, Err(Ok(ContractError::InvalidOrderRef)));
+
+        // Schedules the buffer could never lock for, or that overflow
+        let templates = SorobanVec::from_array(&ctx.env, [
+            InstallmentTemplate { installments_count: 12, interval_secs: 365 * 86400 },
+        ]);
+        let res = client.try_create_invoice(&ctx.merchant, &3000, &templates, &5000, &None);
+        assert_eq!(res, Err(Ok(ContractError::InvalidTemplate)));
+
+        let templates = SorobanVec::from_array(&ctx.env, [
+            InstallmentTemplate { installments_count: 2, interval_secs: u64::MAX },
+        ]);
+        let res = client.try_create_invoice(&ctx.merchant, &3000, &templates, &5000, &None);
+        assert_eq!(res, Err(Ok(ContractError::InvalidTemplate)));
+    }
+
+    #[test]
+    fn test_merchant_authorization_paths() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+
+        // No terms: the merchant has to co-sign
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+        assert_eq!(client.get_plan(&plan_id).auth_path, AuthPath::CoSigned);
+
+        // Within pre-authorized terms: no merchant signature needed
+        client.set_merchant_terms(&ctx.merchant, &MerchantTerms {
+            max_amount: 2000,
+            min_installments: 1,
+            max_installments: 6,
+        });
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &2000, &due_dates, &ctx.options());
+        assert_eq!(client.get_plan(&plan_id).auth_path, AuthPath::PreAuthorized);
+
+        // Above the terms falls back to co-signing
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &2500, &due_dates, &ctx.options());
+        assert_eq!(client.get_plan(&plan_id).auth_path, AuthPath::CoSigned);
+    }
+
+    #[test]
+    fn test_credit_line_cycle() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        client.register_credit_merchant(&ctx.merchant, &true);
+        client.open_credit_line(&ctx.user);
+
+        // Limit is 80% of the 10000 buffer
+        assert_eq!(client.get_available_credit(&ctx.user), 8000);
+        client.draw_credit(&ctx.user, &ctx.merchant, &5000);
+        assert_eq!(client.get_available_credit(&ctx.user), 3000);
+
+        ctx.advance_time(30 * 86400);
+        let statement = client.close_statement(&ctx.user);
+        assert_eq!(statement.balance, 5000);
+        assert_eq!(statement.minimum_due, 500);
+
+        ctx.advance_time(15 * 86400);
+        assert_eq!(client.collect_credit(&ctx.user), 500);
+
+        let line = client.get_credit_line(&ctx.user);
+        assert_eq!(line.outstanding, 4500);
+        assert_eq!(line.locked_shares, 4500);
+        assert_eq!(line.statement.minimum_due, 0);
+    }
+
+    #[test]
+    fn test_credit_collects_remaining_collateral() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let buffer = MockBufferClient::new(&ctx.env, &ctx.buffer);
+
+        client.register_credit_merchant(&ctx.merchant, &true);
+        client.open_credit_line(&ctx.user);
+        client.draw_credit(&ctx.user, &ctx.merchant, &5000);
+        assert_eq!(client.get_credit_line(&ctx.user).draws.get(0).unwrap().lien_expires_at,
+            1000 + STATEMENT_PERIOD_SECS + STATEMENT_GRACE_SECS + LIEN_GRACE_SECS);
+
+        ctx.advance_time(30 * 86400);
+        client.close_statement(&ctx.user);
+        assert_eq!(client.get_credit_line(&ctx.user).draws.get(0).unwrap().lien_expires_at,
+            1000 + 2 * STATEMENT_PERIOD_SECS + STATEMENT_GRACE_SECS + LIEN_GRACE_SECS);
+
+        // Only 200 shares left behind the lien: they are taken, the rest carries over
+        buffer.set_balance(&ctx.user, &0, &200);
+        ctx.advance_time(15 * 86400);
+        assert_eq!(client.collect_credit(&ctx.user), 200);
+        assert_eq!(buffer.debited(&ctx.merchant), 200);
+
+        let line = client.get_credit_line(&ctx.user);
+        assert_eq!(line.outstanding, 4800);
+        assert_eq!(line.statement.minimum_due, 300);
+
+        buffer.set_balance(&ctx.user, &0, &0);
+        let res = client.try_collect_credit(&ctx.user);
+        assert_eq!(res, Err(Ok(ContractError::InsufficientFunds)));
+    }
+
+    #[test]
+    fn test_credit_merchant_settled_by_statements() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let buffer = MockBufferClient::new(&ctx.env, &ctx.buffer);
+
+        client.register_credit_merchant(&ctx.merchant, &true);
+        client.open_credit_line(&ctx.user);
+        client.draw_credit(&ctx.user, &ctx.merchant, &100);
+        assert_eq!(buffer.debited(&ctx.merchant), 0);
+
+        // Each collected minimum goes straight to the merchant
+        ctx.advance_time(STATEMENT_PERIOD_SECS);
+        client.close_statement(&ctx.user);
+        ctx.advance_time(STATEMENT_GRACE_SECS);
+        assert_eq!(client.collect_credit(&ctx.user), 10);
+        assert_eq!(buffer.debited(&ctx.merchant), 10);
+
+        while client.get_credit_line(&ctx.user).outstanding > 0 {
+            ctx.advance_time(STATEMENT_PERIOD_SECS - STATEMENT_GRACE_SECS);
+            client.close_statement(&ctx.user);
+            ctx.advance_time(STATEMENT_GRACE_SECS);
+            client.collect_credit(&ctx.user);
+        }
+
+        // Fully settled once the draw is repaid, with its lien released
+        assert_eq!(buffer.debited(&ctx.merchant), 100);
+        let line = client.get_credit_line(&ctx.user);
+        assert!(line.draws.is_empty());
+        assert_eq!(line.locked_shares, 0);
+        assert!(buffer.get_liens(&ctx.user).is_empty());
+    }
+
+    #[test]
+    fn test_credit_draw_requires_registered_merchant() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        client.open_credit_line(&ctx.user);
+        let res = client.try_draw_credit(&ctx.user, &ctx.merchant, &1000);
+        assert_eq!(res, Err(Ok(ContractError::MerchantNotRegistered)));
+    }
+
+    #[test]
+    fn test_subscription_charges_until_cap() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let sub_id = client.create_subscription(&ctx.user, &ctx.merchant, &100, &1000, &Some(200));
+        assert_eq!(sub_id, String::from_str(&ctx.env, "SUB-000001"));
+
+        // Collection needs no signature from the subscriber
+        let keeper = Address::generate(&ctx.env);
+        let receipt = client.collect_subscription(&sub_id, &keeper);
+        assert!(ctx.env.auths().iter().all(|(address, _)| *address == keeper));
+        assert_eq!(receipt.installment_number, 1);
+        assert_eq!(receipt.merchant_amount, 100);
+
+        // Second period is not due yet
+        assert!(client.try_collect_subscription(&sub_id, &keeper).is_err());
+
+        ctx.advance_time(1000);
+        client.collect_subscription(&sub_id, &keeper);
+
+        let subscription = client.get_subscription(&sub_id);
+        assert_eq!(subscription.charged_total, 200);
+        assert_eq!(subscription.status, SubscriptionStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_subscription_cancelled_by_user() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let sub_id = client.create_subscription(&ctx.user, &ctx.merchant, &100, &1000, &None);
+        client.cancel_subscription(&sub_id);
+        let res = client.try_collect_subscription(&sub_id, &ctx.merchant);
+        assert_eq!(res.err(), Some(Ok(ContractError::SubscriptionNotActive)));
+    }
+
+    #[test]
+    fn test_subscription_pays_keeper_bounty() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let keeper = Address::generate(&ctx.env);
+
+        let token_admin = Address::generate(&ctx.env);
+        let token = ctx.env.register_stellar_asset_contract_v2(token_admin).address();
+        StellarAssetClient::new(&ctx.env, &token).mint(&ctx.admin, &100);
+        client.set_payment_token(&token);
+        client.fund_bounty_pool(&ctx.admin, &100);
+
+        client.update_config(&BridgeConfig {
+            max_forbearance_count: 2,
+            max_forbearance_days: 90,
+            keeper_bounty_bps: 100,
+            keeper_bounty_flat: 0,
+        });
+
+        let sub_id = client.create_subscription(&ctx.user, &ctx.merchant, &500, &1000, &None);
+        let receipt = client.collect_subscription(&sub_id, &keeper);
+
+        // 1% of the 500 charge
+        assert_eq!(receipt.fee_amount, 5);
+        assert_eq!(TokenClient::new(&ctx.env, &token).balance(&keeper), 5);
+        assert_eq!(client.get_bounty_pool(), 95);
+    }
+
+    #[test]
+    fn test_escrow_holds_until_delivery() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let token = ctx.payment_token();
+        let keeper = Address::generate(&ctx.env);
+
+        StellarAssetClient::new(&ctx.env, &token.address).mint(&ctx.admin, &100);
+        client.fund_bounty_pool(&ctx.admin, &100);
+        client.update_config(&BridgeConfig {
+            max_forbearance_count: 2,
+            max_forbearance_days: 90,
+            keeper_bounty_bps: 50,
+            keeper_bounty_flat: 0,
+        });
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000]);
+        let plan_id = client.create_plan(
+            &ctx.user, &ctx.merchant, &2000, &due_dates,
+            &PlanOptions { delivery_deadline: Some(10000), ..ctx.options() },
+        );
+
+        // Collected when due, in the plan's preference order, into bridge custody
+        ctx.advance_time(1000);
+        let receipt = client.collect_installment(&plan_id, &1, &ctx.merchant, &keeper);
+        assert_eq!(receipt.source, PaymentSource::Available);
+        assert_eq!(receipt.fee_amount, 5);
+        assert_eq!(client.get_plan(&plan_id).installments.get(0).unwrap().status, InstallmentStatus::Held);
+        assert_eq!(client.get_escrow(&plan_id).held_amount, 1000);
+        assert_eq!(token.balance(&ctx.bridge), 1000 + 95);
+        assert_eq!(token.balance(&ctx.merchant), 0);
+
+        // Held payments cannot be collected again
+        let res = client.try_collect_installment(&plan_id, &1, &ctx.merchant, &keeper);
+        assert_eq!(res.err(), Some(Ok(ContractError::AlreadyPaid)));
+
+        // Buyer confirms delivery and the merchant is paid from custody
+        assert_eq!(client.release_escrow(&plan_id), 1000);
+        assert_eq!(token.balance(&ctx.merchant), 1000);
+        assert_eq!(token.balance(&ctx.bridge), 95);
+        assert_eq!(client.get_plan(&plan_id).installments.get(0).unwrap().status, InstallmentStatus::Paid);
+
+        // Later installments go straight to the merchant
+        ctx.advance_time(1000);
+        client.collect_installment(&plan_id, &2, &ctx.merchant, &keeper);
+        assert_eq!(token.balance(&ctx.merchant), 2000);
+        assert_eq!(client.get_plan(&plan_id).status, PlanStatus::Completed);
+    }
+
+    #[test]
+    fn test_escrow_release_survives_drained_buffer() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let buffer = MockBufferClient::new(&ctx.env, &ctx.buffer);
+        let token = ctx.payment_token();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000]);
+        let plan_id = client.create_plan(
+            &ctx.user, &ctx.merchant, &2000, &due_dates,
+            &PlanOptions { delivery_deadline: Some(10000), ..ctx.options() },
+        );
+
+        ctx.advance_time(1000);
+        client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+
+        // The buyer empties their buffer; what was collected is still held
+        buffer.set_balance(&ctx.user, &0, &0);
+        assert_eq!(client.release_escrow(&plan_id), 1000);
+        assert_eq!(token.balance(&ctx.merchant), 1000);
+    }
+
+    #[test]
+    fn test_escrow_requires_payment_token() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64]);
+        let res = client.try_create_plan(
+            &ctx.user, &ctx.merchant, &1000, &due_dates,
+            &PlanOptions { delivery_deadline: Some(10000), ..ctx.options() },
+        );
+        assert_eq!(res, Err(Ok(ContractError::NoPaymentToken)));
+    }
+
+    #[test]
+    fn test_escrow_refunded_on_dispute() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let token = ctx.payment_token();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000]);
+        let plan_id = client.create_plan(
+            &ctx.user, &ctx.merchant, &2000, &due_dates,
+            &PlanOptions { delivery_deadline: Some(10000), ..ctx.options() },
+        );
+
+        ctx.advance_time(1000);
+        client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+
+        client.open_dispute(&plan_id, &1);
+        let released = client.resolve_dispute(&plan_id, &DisputeOutcome::FullCancel);
+        assert_eq!(released, 1000);
+
+        // The held payment goes back to the buyer, the rest of the lien is released
+        let plan = client.get_plan(&plan_id);
+        assert_eq!(plan.installments.get(0).unwrap().status, InstallmentStatus::Waived);
+        assert_eq!(client.get_escrow(&plan_id).held_amount, 0);
+        assert_eq!(token.balance(&ctx.user), 1000);
+        assert_eq!(token.balance(&ctx.bridge), 0);
+        assert!(MockBufferClient::new(&ctx.env, &ctx.buffer).get_liens(&ctx.user).is_empty());
+    }
+
+    #[test]
+    fn test_split_payees_leave_no_dust() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let buffer = MockBufferClient::new(&ctx.env, &ctx.buffer);
+
+        let seller = Address::generate(&ctx.env);
+        let platform = Address::generate(&ctx.env);
+        let shipping = Address::generate(&ctx.env);
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &1000, &due_dates, &ctx.options());
+
+        let payees = SorobanVec::from_array(&ctx.env, [
+            Payee { payee: seller.clone(), bps: 3333 },
+            Payee { payee: platform.clone(), bps: 3333 },
+            Payee { payee: shipping.clone(), bps: 3334 },
+        ]);
+        client.set_plan_payees(&plan_id, &payees);
+
+        ctx.advance_time(1000);
+        let receipt = client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+
+        assert_eq!(buffer.debited(&seller), 333);
+        assert_eq!(buffer.debited(&platform), 333);
+        assert_eq!(buffer.debited(&shipping), 334);
+        assert_eq!(buffer.debited(&ctx.merchant), 0);
+        assert_eq!(receipt.shares_burned, 1000);
+        assert_eq!(receipt.merchant_amount, 1000);
+    }
+
+    #[test]
+    fn test_split_payees_must_add_up() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &1000, &due_dates, &ctx.options());
+
+        let payees = SorobanVec::from_array(&ctx.env, [
+            Payee { payee: ctx.merchant.clone(), bps: 9000 },
+        ]);
+        let res = client.try_set_plan_payees(&plan_id, &payees);
+        assert_eq!(res, Err(Ok(ContractError::InvalidPayees)));
+    }
+
+    #[test]
+    fn test_guarantor_backs_plan() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let buffer = MockBufferClient::new(&ctx.env, &ctx.buffer);
+        let guarantor = Address::generate(&ctx.env);
+
+        // 15000 exceeds the user's LTV alone but fits the combined balances
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(
+            &ctx.user, &ctx.merchant, &15000, &due_dates,
+            &PlanOptions { guarantor: Some(guarantor.clone()), ..ctx.options() },
+        );
+
+        let plan = client.get_plan(&plan_id);
+        assert_eq!(plan.protected_shares, 10000);
+        assert_eq!(plan.guarantor_shares, 5000);
+
+        // User's shares exhausted: the guarantor's protected shares pay
+        buffer.set_balance(&ctx.user, &0, &0);
+        buffer.set_balance(&guarantor, &0, &5000);
+
+        ctx.advance_time(1000);
+        let receipt = client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+        assert_eq!(receipt.source, PaymentSource::Protected);
+        assert_eq!(receipt.merchant_amount, 5000);
+        assert_eq!(client.get_plan(&plan_id).guarantor_shares, 0);
+    }
+
+    #[test]
+    fn test_guarantor_released_on_partial_refund() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let guarantor = Address::generate(&ctx.env);
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(
+            &ctx.user, &ctx.merchant, &15000, &due_dates,
+            &PlanOptions { guarantor: Some(guarantor.clone()), ..ctx.options() },
+        );
+
+        // A third of the balance forgiven frees a third of both liens
+        client.open_dispute(&plan_id, &1);
+        let released = client.resolve_dispute(&plan_id, &DisputeOutcome::PartialRefund(5000));
+        let plan = client.get_plan(&plan_id);
+        assert_eq!(released, 3333);
+        assert_eq!(plan.protected_shares, 6667);
+        assert_eq!(plan.guarantor_shares, 3334);
+    }
+
+    #[test]
+    fn test_guarantor_cannot_be_user() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64]);
+        let res = client.try_create_plan(
+            &ctx.user, &ctx.merchant, &1000, &due_dates,
+            &PlanOptions { guarantor: Some(ctx.user.clone()), ..ctx.options() },
+        );
+        assert_eq!(res, Err(Ok(ContractError::InvalidGuarantor)));
+    }
+
+    #[test]
+    fn test_keeper_bounty_paid_from_pool() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let keeper = Address::generate(&ctx.env);
+
+        let token_admin = Address::generate(&ctx.env);
+        let token = ctx.env.register_stellar_asset_contract_v2(token_admin).address();
+        StellarAssetClient::new(&ctx.env, &token).mint(&ctx.admin, &100);
+        client.set_payment_token(&token);
+        client.fund_bounty_pool(&ctx.admin, &100);
+
+        client.update_config(&BridgeConfig {
+            max_forbearance_count: 2,
+            max_forbearance_days: 90,
+            keeper_bounty_bps: 50,
+            keeper_bounty_flat: 5,
+        });
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        // Not due yet: no collection, no bounty
+        assert!(client.try_collect_installment(&plan_id, &1, &ctx.merchant, &keeper).is_err());
+
+        ctx.advance_time(1000);
+        client.collect_installment(&plan_id, &1, &ctx.merchant, &keeper);
+
+        // 0.5% of 1000 plus 5 flat
+        assert_eq!(TokenClient::new(&ctx.env, &token).balance(&keeper), 10);
+        assert_eq!(client.get_bounty_pool(), 90);
+        assert_eq!(client.get_receipt(&plan_id, &1).fee_amount, 10);
+    }
+
+    #[test]
+    fn test_buffer_read_from_config() {
+        let ctx = TestContext::new();
+
+        // A bridge without a configured buffer cannot create plans
+        let bridge = ctx.env.register(BridgeContract, (ctx.admin.clone(),));
+        let client = BridgeContractClient::new(&ctx.env, &bridge);
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64]);
+        let res = client.try_create_plan(&ctx.user, &ctx.merchant, &1000, &due_dates, &ctx.options());
+        assert_eq!(res, Err(Ok(ContractError::NoBufferContract)));
+
+        // Once set, the configured buffer backs every call
+        client.set_buffer_contract(&ctx.buffer);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &1000, &due_dates, &ctx.options());
+        assert_eq!(client.get_plan(&plan_id).protected_shares, 1000);
+    }
+
+    #[test]
+    fn test_keeper_cannot_redirect_payment() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        let keeper = Address::generate(&ctx.env);
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &1000, &due_dates, &ctx.options());
+
+        ctx.advance_time(1000);
+        let res = client.try_collect_installment(&plan_id, &1, &keeper, &keeper);
+        assert_eq!(res.err(), Some(Ok(ContractError::Unauthorized)));
+    }
+
+    // Next bridge release used by the upgrade test: schema 2 moves the
+    // bounty pool from instance to persistent storage. Tests cannot build
+    // Wasm, so it is registered natively over the upgraded contract, which
+    // keeps the contract's storage like an on-chain Wasm update does.
+    #[contract]
+    pub struct BridgeV2;
+
+    #[contractimpl]
+    impl BridgeV2 {
+        pub fn migrate(env: Env) -> u32 {
+            let from = BridgeContract::get_schema_version(env.clone());
+            assert!(from < 2, "Already migrated");
+
+            let pool = BridgeContract::get_bounty_pool(env.clone());
+            env.storage().instance().remove(&DataKey::BountyPool);
+            env.storage().persistent().set(&DataKey::BountyPool, &pool);
+
+            env.storage().instance().set(&DataKey::SchemaVersion, &2u32);
+            2
+        }
+
+        pub fn get_bounty_pool(env: Env) -> i128 {
+            env.storage().persistent().get(&DataKey::BountyPool).unwrap_or(0)
+        }
+
+        pub fn get_plan(env: Env, plan_id: String) -> BridgePlan {
+            BridgeContract::get_plan(env, plan_id).unwrap()
+        }
+
+        pub fn get_user_plans(env: Env, user: Address) -> Vec<String> {
+            BridgeContract::get_user_plans(env, user)
+        }
+    }
+
+    #[test]
+    fn test_upgrade_keeps_plans_and_pool() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let token_admin = Address::generate(&ctx.env);
+        let token = ctx.env.register_stellar_asset_contract_v2(token_admin).address();
+        StellarAssetClient::new(&ctx.env, &token).mint(&ctx.admin, &500);
+        client.set_payment_token(&token);
+        client.fund_bounty_pool(&ctx.admin, &500);
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+        ctx.advance_time(1500);
+        client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+
+        let wasm_hash = ctx.env.deployer().upload_contract_wasm(Bytes::new(&ctx.env));
+        client.upgrade(&wasm_hash);
+        ctx.env.register_at(&ctx.bridge, BridgeV2, ());
+
+        let v2 = BridgeV2Client::new(&ctx.env, &ctx.bridge);
+        assert_eq!(v2.migrate(), 2);
+        assert_eq!(v2.get_bounty_pool(), 500);
+        assert_eq!(TokenClient::new(&ctx.env, &token).balance(&ctx.bridge), 500);
+
+        let plan = v2.get_plan(&plan_id);
+        assert_eq!(plan.status, PlanStatus::Active);
+        assert_eq!(plan.protected_shares, 2000);
+        assert_eq!(plan.installments.get(0).unwrap().status, InstallmentStatus::Paid);
+        assert_eq!(v2.get_user_plans(&ctx.user).len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_current_schema_fails() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        assert_eq!(client.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(client.try_migrate(), Err(Ok(ContractError::AlreadyMigrated)));
+    }
+
+    #[test]
+    fn test_bump_plan_outlives_ttl() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        let plan_ttl = || ctx.env.as_contract(&ctx.bridge, || {
+            ctx.env.storage().persistent().get_ttl(&DataKey::Plan(plan_id.clone()))
+        });
+        assert_eq!(plan_ttl(), PERSISTENT_BUMP_AMOUNT);
+
+        // Twice the original TTL goes by, bumping before each window runs out
+        for _ in 0..4 {
+            ctx.env.ledger().with_mut(|li| li.sequence_number += PERSISTENT_BUMP_AMOUNT / 2);
+            assert_eq!(plan_ttl(), PERSISTENT_BUMP_AMOUNT / 2);
+            client.bump_plan(&plan_id);
+            assert_eq!(plan_ttl(), PERSISTENT_BUMP_AMOUNT);
+        }
+
+        assert_eq!(client.get_plan(&plan_id).total_amount, 3000);
+        assert_eq!(client.get_user_plans(&ctx.user).len(), 1);
+    }
+
+    #[test]
+    fn test_collect_extends_plan_ttl() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let due_dates = SorobanVec::from_array(&ctx.env, [2000u64, 3000, 4000]);
+        let plan_id = client.create_plan(&ctx.user, &ctx.merchant, &3000, &due_dates, &ctx.options());
+
+        // The buffer keeps its own instance alive; the mock does not, so bump it by hand
+        ctx.env.as_contract(&ctx.buffer, || {
+            ctx.env.storage().instance().extend_ttl(PERSISTENT_BUMP_AMOUNT, PERSISTENT_BUMP_AMOUNT);
+        });
+        ctx.env.ledger().with_mut(|li| li.sequence_number += PERSISTENT_BUMP_AMOUNT - DAY_IN_LEDGERS);
+        ctx.advance_time(1500);
+        client.collect_installment(&plan_id, &1, &ctx.merchant, &ctx.user);
+
+        ctx.env.as_contract(&ctx.bridge, || {
+            let storage = ctx.env.storage();
+            assert_eq!(storage.persistent().get_ttl(&DataKey::Plan(plan_id.clone())), PERSISTENT_BUMP_AMOUNT);
+            assert_eq!(storage.persistent().get_ttl(&DataKey::Receipt(plan_id.clone(), 1)), PERSISTENT_BUMP_AMOUNT);
+            assert_eq!(storage.instance().get_ttl(), INSTANCE_BUMP_AMOUNT);
+        });
+    }
+}
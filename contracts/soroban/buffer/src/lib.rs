@@ -2,7 +2,9 @@
 #![allow(unused_variables)] 
 
 use soroban_sdk::{
-    contract, contractimpl, contracttype, Address, Env, Symbol, Vec, vec
+    auth::{ContractContext, InvokerContractAuthEntry, SubContractInvocation},
+    contract, contractimpl, contracttype, token::TokenClient, Address, BytesN, Env, IntoVal, Symbol,
+    TryFromVal, Val, Vec, vec
 };
 
 mod vault_import {
@@ -14,6 +16,24 @@ const MIN_AMOUNT: i128 = 1;
 const DEFAULT_SLIPPAGE_BPS: i128 = 50;
 const DEFAULT_MIN_INTERVAL_SECS: u64 = 2;
 const BPS_DIVISOR: i128 = 10000;
+const MAX_LOCK_DURATION_SECS: u64 = 730 * 86400;
+const MAX_SLIPPAGE_BPS: i128 = 1000;
+const MAX_MIN_INTERVAL_SECS: u64 = 86400;
+const MIN_CONFIG_DELAY_SECS: u64 = 86400;
+const DEFAULT_CONFIG_DELAY_SECS: u64 = 2 * 86400;
+const MAX_CONFIG_DELAY_SECS: u64 = 30 * 86400;
+const PRICE_SCALE: i128 = 10_000_000;
+const DEFAULT_BREAKER_DROP_BPS: i128 = 500;
+const DEFAULT_BREAKER_WINDOW_SECS: u64 = 86400;
+const MAX_BREAKER_DROP_BPS: i128 = 5000;
+const MAX_BREAKER_WINDOW_SECS: u64 = 30 * 86400;
+const SCHEMA_VERSION: u32 = 3;
+
+const DAY_IN_LEDGERS: u32 = 17280; // ~5s per ledger
+const PERSISTENT_BUMP_AMOUNT: u32 = 120 * DAY_IN_LEDGERS; // balances and liens
+const PERSISTENT_LIFETIME_THRESHOLD: u32 = PERSISTENT_BUMP_AMOUNT - 30 * DAY_IN_LEDGERS; // re-bumped under 90 days left
+const INSTANCE_BUMP_AMOUNT: u32 = PERSISTENT_BUMP_AMOUNT; // never shorter than the liens it governs
+const INSTANCE_LIFETIME_THRESHOLD: u32 = PERSISTENT_LIFETIME_THRESHOLD; // checked on every call
 
 #[contracttype]
 #[derive(Clone)]
@@ -32,6 +52,7 @@ pub struct DepositResult {
     pub amount_deposited: i128,
     pub new_available_balance: i128,
     pub timestamp: u64,
+    pub breaker_tripped: bool, // Price drop seen: the breaker tripped and nothing was deposited
 }
 
 #[contracttype]
@@ -46,11 +67,34 @@ pub struct WithdrawResult {
 #[contracttype]
 #[derive(Clone)]
 pub struct LockResult {
+    pub lien_id: u64,
     pub shares_locked: i128,
     pub new_available: i128,
     pub new_protected: i128,
 }
 
+#[contracttype]
+#[derive(Clone)]
+pub struct Lien {
+    pub id: u64,
+    pub user: Address,
+    pub owner: Address,
+    pub shares: i128,
+    pub expires_at: u64,
+    pub created_at: u64,
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct Consumer {
+    pub active: bool,
+    pub debt_ceiling: i128,
+    pub locked_shares: i128,
+    pub total_locked: i128,
+    pub total_debited: i128,
+    pub added_at: u64,
+}
+
 #[contracttype]
 #[derive(Clone)]
 pub struct ContractConfig {
@@ -58,18 +102,104 @@ pub struct ContractConfig {
     pub slippage_tolerance_bps: i128,
 }
 
+// What each pause flag blocks. Unlisted entrypoints are never paused, so
+// users can always get collateral back (unlock_shares, reclaim_expired_lock).
+//
+//   deposits     deposit
+//   withdrawals  withdraw_available
+//   locks        lock_shares
+//   debits       debit_available, debit_protected
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PauseFlags {
+    pub deposits: bool,
+    pub withdrawals: bool,
+    pub locks: bool,
+    pub debits: bool,
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct BreakerConfig {
+    pub max_drop_bps: i128,
+    pub window_secs: u64,
+}
+
+// Share price (total_managed / total_shares, scaled by PRICE_SCALE) that
+// later observations are compared against
+#[contracttype]
+#[derive(Clone)]
+pub struct PriceReference {
+    pub price: i128,
+    pub recorded_at: u64,
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct BreakerTrip {
+    pub reference_price: i128,
+    pub price: i128,
+    pub drop_bps: i128,
+    pub tripped_at: u64,
+    pub paused_deposits: bool, // Flags the trip set, so a reset leaves a manual pause alone
+    pub paused_locks: bool,
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct EmergencyExit {
+    pub shares_withdrawn: i128,
+    pub assets_received: i128,
+    pub executed_at: u64,
+    pub holds_pause: bool, // Every pause flag stays set until the admin lifts it
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct PendingConfig {
+    pub config: ContractConfig,
+    pub config_delay_secs: u64,
+    pub proposed_by: Address,
+    pub proposed_at: u64,
+    pub executable_at: u64,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    Pauser,
+    ConfigManager,
+    StrategyManager,
+    Upgrader,
+}
+
 #[contracttype]
 #[derive(Clone)]
 pub enum DataKey {
     Admin,
+    PendingAdmin,
+    RoleMember(Role, Address),
     Vault,
     Asset,
-    Bridge,
-    Paused,
+    Consumer(Address),
+    Consumers,
+    Allowance(Address, Address), // (user, consumer) shares the consumer may debit
+    Paused, // Schema 1 all-or-nothing switch, replaced by PauseFlags
+    PauseFlags,
+    EmergencyExit,
+    BreakerConfig,
+    PriceReference,
+    BreakerTrip,
     Config,
+    ConfigDelay,
+    PendingConfig,
     Balance(Address),
     TotalStats,
     BlendStrategy,
+    Lien(u64),
+    LienCounter,
+    UserLiens(Address),
+    SchemaVersion,
 }
 
 #[contracttype]
@@ -87,6 +217,7 @@ pub struct BufferContract;
 #[contractimpl]
 impl BufferContract {
     pub fn __constructor(env: Env, admin: Address, vault: Address, asset: Address, blend_strategy: Address) {
+        Self::extend_instance(&env);
         admin.require_auth();
         
         Self::validate_non_zero_address(&env, &admin);
@@ -99,13 +230,20 @@ impl BufferContract {
         storage.set(&DataKey::Vault, &vault);
         storage.set(&DataKey::Asset, &asset);
         storage.set(&DataKey::BlendStrategy, &blend_strategy);
-        storage.set(&DataKey::Paused, &false);
+        storage.set(&DataKey::PauseFlags, &PauseFlags {
+            deposits: false,
+            withdrawals: false,
+            locks: false,
+            debits: false,
+        });
+        storage.set(&DataKey::SchemaVersion, &SCHEMA_VERSION);
         storage.set(&DataKey::Config, &ContractConfig {
             min_deposit_interval: DEFAULT_MIN_INTERVAL_SECS,
             slippage_tolerance_bps: DEFAULT_SLIPPAGE_BPS,
         });
+        storage.set(&DataKey::ConfigDelay, &DEFAULT_CONFIG_DELAY_SECS);
         
-        env.storage().persistent().set(&DataKey::TotalStats, &TotalStats {
+        Self::write_persistent(&env, &DataKey::TotalStats, &TotalStats {
             total_available: 0,
             total_protected: 0,
             total_deposited: 0,
@@ -118,58 +256,481 @@ impl BufferContract {
         );
     }
 
-    pub fn set_bridge(env: Env, bridge: Address) {
+    pub fn propose_admin(env: Env, new_admin: Address) {
+        Self::extend_instance(&env);
+        let admin: Address = env.storage().instance()
+            .get(&DataKey::Admin)
+            .unwrap_or_else(|| panic!("Admin not set"));
+        admin.require_auth();
+        
+        Self::validate_non_zero_address(&env, &new_admin);
+        
+        // Takes effect only once the new admin accepts, so a typo can't lock the contract
+        env.storage().instance().set(&DataKey::PendingAdmin, &new_admin);
+        
+        env.events().publish((Symbol::new(&env, "admin_proposed"), admin), new_admin);
+    }
+
+    pub fn accept_admin(env: Env) {
+        Self::extend_instance(&env);
+        let new_admin: Address = env.storage().instance()
+            .get(&DataKey::PendingAdmin)
+            .unwrap_or_else(|| panic!("No pending admin"));
+        new_admin.require_auth();
+        
+        let old_admin: Address = env.storage().instance()
+            .get(&DataKey::Admin)
+            .unwrap_or_else(|| panic!("Admin not set"));
+        
+        env.storage().instance().set(&DataKey::Admin, &new_admin);
+        env.storage().instance().remove(&DataKey::PendingAdmin);
+        
+        env.events().publish((Symbol::new(&env, "admin_transferred"), old_admin), new_admin);
+    }
+
+    pub fn cancel_admin_proposal(env: Env) {
+        Self::extend_instance(&env);
+        let admin: Address = env.storage().instance()
+            .get(&DataKey::Admin)
+            .unwrap_or_else(|| panic!("Admin not set"));
+        admin.require_auth();
+        
+        let pending: Address = env.storage().instance()
+            .get(&DataKey::PendingAdmin)
+            .unwrap_or_else(|| panic!("No pending admin"));
+        env.storage().instance().remove(&DataKey::PendingAdmin);
+        
+        env.events().publish((Symbol::new(&env, "admin_proposal_cancelled"), admin), pending);
+    }
+
+    pub fn grant_role(env: Env, role: Role, account: Address) {
+        Self::extend_instance(&env);
+        let admin: Address = env.storage().instance()
+            .get(&DataKey::Admin)
+            .unwrap_or_else(|| panic!("Admin not set"));
+        admin.require_auth();
+        
+        Self::validate_non_zero_address(&env, &account);
+        
+        let key = DataKey::RoleMember(role, account.clone());
+        if Self::extend_persistent(&env, &key) {
+            panic!("Role already granted");
+        }
+        Self::write_persistent(&env, &key, &true);
+        
+        env.events().publish((Symbol::new(&env, "role_granted"), role, account), admin);
+    }
+
+    pub fn revoke_role(env: Env, role: Role, account: Address) {
+        Self::extend_instance(&env);
+        let admin: Address = env.storage().instance()
+            .get(&DataKey::Admin)
+            .unwrap_or_else(|| panic!("Admin not set"));
+        admin.require_auth();
+        
+        let key = DataKey::RoleMember(role, account.clone());
+        if !Self::extend_persistent(&env, &key) {
+            panic!("Role not granted");
+        }
+        env.storage().persistent().remove(&key);
+        
+        env.events().publish((Symbol::new(&env, "role_revoked"), role, account), admin);
+    }
+
+    pub fn renounce_role(env: Env, role: Role, account: Address) {
+        Self::extend_instance(&env);
+        account.require_auth();
+        
+        let key = DataKey::RoleMember(role, account.clone());
+        if !Self::extend_persistent(&env, &key) {
+            panic!("Role not granted");
+        }
+        env.storage().persistent().remove(&key);
+        
+        env.events().publish((Symbol::new(&env, "role_revoked"), role, account.clone()), account);
+    }
+
+    pub fn add_consumer(env: Env, consumer: Address, debt_ceiling: i128) {
+        Self::extend_instance(&env);
+        let admin: Address = env.storage().instance()
+            .get(&DataKey::Admin)
+            .unwrap_or_else(|| panic!("Admin not set"));
+        admin.require_auth();
+        
+        Self::validate_non_zero_address(&env, &consumer);
+        
+        if debt_ceiling < 0 {
+            panic!("Invalid amount");
+        }
+        
+        // Re-adding a revoked consumer keeps its counters and liens
+        let key = DataKey::Consumer(consumer.clone());
+        let entry = match Self::read_persistent::<Consumer>(&env, &key) {
+            Some(mut existing) => {
+                if existing.active {
+                    panic!("Consumer already registered");
+                }
+                existing.active = true;
+                existing.debt_ceiling = debt_ceiling;
+                existing
+            }
+            None => {
+                let mut consumers: Vec<Address> = env.storage().instance()
+                    .get(&DataKey::Consumers)
+                    .unwrap_or(Vec::new(&env));
+                consumers.push_back(consumer.clone());
+                env.storage().instance().set(&DataKey::Consumers, &consumers);
+                
+                Consumer {
+                    active: true,
+                    debt_ceiling,
+                    locked_shares: 0,
+                    total_locked: 0,
+                    total_debited: 0,
+                    added_at: env.ledger().timestamp(),
+                }
+            }
+        };
+        Self::write_persistent(&env, &key, &entry);
+        
+        env.events().publish((Symbol::new(&env, "consumer_added"), consumer), debt_ceiling);
+    }
+
+    pub fn set_consumer_ceiling(env: Env, consumer: Address, debt_ceiling: i128) {
+        Self::extend_instance(&env);
         let admin: Address = env.storage().instance()
             .get(&DataKey::Admin)
             .unwrap_or_else(|| panic!("Admin not set"));
         admin.require_auth();
         
-        Self::validate_non_zero_address(&env, &bridge);
-        env.storage().instance().set(&DataKey::Bridge, &bridge);
+        if debt_ceiling < 0 {
+            panic!("Invalid amount");
+        }
+        
+        // Lowering the ceiling below current locks only blocks new locks
+        let mut entry = Self::get_consumer(env.clone(), consumer.clone());
+        entry.debt_ceiling = debt_ceiling;
+        Self::write_persistent(&env, &DataKey::Consumer(consumer.clone()), &entry);
         
-        env.events().publish((Symbol::new(&env, "bridge_set"),), bridge);
+        env.events().publish((Symbol::new(&env, "consumer_capped"), consumer), debt_ceiling);
     }
 
-    pub fn update_config(env: Env, min_deposit_interval: u64, slippage_tolerance_bps: i128) {
+    pub fn revoke_consumer(env: Env, consumer: Address) {
+        Self::extend_instance(&env);
         let admin: Address = env.storage().instance()
             .get(&DataKey::Admin)
             .unwrap_or_else(|| panic!("Admin not set"));
         admin.require_auth();
         
-        env.storage().instance().set(&DataKey::Config, &ContractConfig {
-            min_deposit_interval,
-            slippage_tolerance_bps,
+        // Existing liens can still be unlocked or settled by the consumer
+        let mut entry = Self::get_consumer(env.clone(), consumer.clone());
+        entry.active = false;
+        Self::write_persistent(&env, &DataKey::Consumer(consumer.clone()), &entry);
+        
+        env.events().publish((Symbol::new(&env, "consumer_revoked"), consumer), entry.locked_shares);
+    }
+
+    pub fn propose_config(
+        env: Env,
+        caller: Address,
+        min_deposit_interval: u64,
+        slippage_tolerance_bps: i128,
+        config_delay_secs: u64,
+    ) -> u64 {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::ConfigManager);
+        
+        if !(0..=MAX_SLIPPAGE_BPS).contains(&slippage_tolerance_bps) {
+            panic!("Invalid slippage");
+        }
+        if min_deposit_interval > MAX_MIN_INTERVAL_SECS {
+            panic!("Invalid interval");
+        }
+        if !(MIN_CONFIG_DELAY_SECS..=MAX_CONFIG_DELAY_SECS).contains(&config_delay_secs) {
+            panic!("Invalid delay");
+        }
+        if env.storage().instance().has(&DataKey::PendingConfig) {
+            panic!("Config change pending");
+        }
+        
+        // The current delay applies, so shortening it also has to wait
+        let now = env.ledger().timestamp();
+        let executable_at = checked_add_u64(&env, now, Self::get_config_delay(env.clone()));
+        
+        env.storage().instance().set(&DataKey::PendingConfig, &PendingConfig {
+            config: ContractConfig {
+                min_deposit_interval,
+                slippage_tolerance_bps,
+            },
+            config_delay_secs,
+            proposed_by: caller.clone(),
+            proposed_at: now,
+            executable_at,
         });
         
         env.events().publish(
-            (Symbol::new(&env, "config_updated"),),
-            (min_deposit_interval, slippage_tolerance_bps)
+            (Symbol::new(&env, "config_proposed"), caller),
+            (min_deposit_interval, slippage_tolerance_bps, config_delay_secs, executable_at)
+        );
+        
+        executable_at
+    }
+
+    pub fn execute_config(env: Env, caller: Address) -> ContractConfig {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::ConfigManager);
+        
+        let pending: PendingConfig = env.storage().instance()
+            .get(&DataKey::PendingConfig)
+            .unwrap_or_else(|| panic!("No pending config"));
+        
+        if env.ledger().timestamp() < pending.executable_at {
+            panic!("Timelock active");
+        }
+        
+        env.storage().instance().set(&DataKey::Config, &pending.config);
+        env.storage().instance().set(&DataKey::ConfigDelay, &pending.config_delay_secs);
+        env.storage().instance().remove(&DataKey::PendingConfig);
+        
+        env.events().publish(
+            (Symbol::new(&env, "config_executed"), caller),
+            (pending.config.min_deposit_interval, pending.config.slippage_tolerance_bps, pending.config_delay_secs)
         );
+        
+        pending.config
+    }
+
+    pub fn cancel_config(env: Env, caller: Address) {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::ConfigManager);
+        
+        let pending: PendingConfig = env.storage().instance()
+            .get(&DataKey::PendingConfig)
+            .unwrap_or_else(|| panic!("No pending config"));
+        env.storage().instance().remove(&DataKey::PendingConfig);
+        
+        env.events().publish((Symbol::new(&env, "config_cancelled"), caller), pending.proposed_by);
     }
 
-    pub fn emergency_pause(env: Env) {
+    pub fn emergency_pause(env: Env, caller: Address) {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::Pauser);
+        
+        env.storage().instance().set(&DataKey::PauseFlags, &PauseFlags {
+            deposits: true,
+            withdrawals: true,
+            locks: true,
+            debits: true,
+        });
+        env.events().publish((Symbol::new(&env, "paused"),), caller);
+    }
+
+    pub fn emergency_unpause(env: Env, caller: Address) {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::Pauser);
+        
+        env.storage().instance().set(&DataKey::PauseFlags, &PauseFlags {
+            deposits: false,
+            withdrawals: false,
+            locks: false,
+            debits: false,
+        });
+        
+        // The flags held by an emergency exit are the admin's to lift
+        if let Some(mut exit) = Self::get_emergency_exit(env.clone()) {
+            if caller == Self::get_admin(env.clone()) {
+                exit.holds_pause = false;
+                env.storage().instance().set(&DataKey::EmergencyExit, &exit);
+            }
+        }
+        env.events().publish((Symbol::new(&env, "unpaused"),), caller);
+    }
+
+    pub fn set_pause_flags(env: Env, caller: Address, flags: PauseFlags) {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::Pauser);
+        
+        env.storage().instance().set(&DataKey::PauseFlags, &flags);
+        env.events().publish((Symbol::new(&env, "pause_updated"), caller), flags);
+    }
+
+    pub fn emergency_withdraw(env: Env) -> EmergencyExit {
+        Self::extend_instance(&env);
         let admin: Address = env.storage().instance()
             .get(&DataKey::Admin)
             .unwrap_or_else(|| panic!("Admin not set"));
         admin.require_auth();
+        Self::require_current_schema(&env);
+        
+        if env.storage().instance().has(&DataKey::EmergencyExit) {
+            panic!("Emergency exit active");
+        }
+        
+        let vault: Address = env.storage().instance()
+            .get(&DataKey::Vault)
+            .unwrap_or_else(|| panic!("Vault not configured"));
+        let vault_client = DeFindexVaultClient::new(&env, &vault);
+        let buffer = env.current_contract_address();
+        
+        let shares = vault_client.balance(&buffer);
+        if shares < MIN_AMOUNT {
+            panic!("Invalid amount");
+        }
+        
+        // Everything comes back to the buffer as the underlying asset; the
+        // rate is frozen here and used for every redemption afterwards
+        let amounts = vault_client.withdraw(&shares, &vec![&env, 0], &buffer);
+        let exit = EmergencyExit {
+            shares_withdrawn: shares,
+            assets_received: amounts.get(0).unwrap_or(0),
+            executed_at: env.ledger().timestamp(),
+            holds_pause: true,
+        };
+        env.storage().instance().set(&DataKey::EmergencyExit, &exit);
+        
+        env.events().publish(
+            (Symbol::new(&env, "emergency_withdrawn"), admin),
+            (exit.shares_withdrawn, exit.assets_received)
+        );
+        
+        exit
+    }
+
+    pub fn redeem_emergency(env: Env, user: Address) -> i128 {
+        Self::extend_instance(&env);
+        user.require_auth();
+        
+        let exit = Self::get_emergency_exit(env.clone())
+            .unwrap_or_else(|| panic!("No emergency exit"));
+        
+        let mut bal = Self::get_balance_or_default(env.clone(), user.clone());
+        let shares = bal.available_shares;
+        if shares < MIN_AMOUNT {
+            panic!("Insufficient available");
+        }
+        
+        // Protected shares stay put until their lien holder releases or redeems them
+        let amount = mul_div(&env, shares, exit.assets_received, exit.shares_withdrawn);
+        
+        bal.available_shares = 0;
+        bal.version = checked_add_u64(&env, bal.version, 1);
+        Self::write_persistent(&env, &DataKey::Balance(user.clone()), &bal);
+        
+        Self::update_total_stats(&env, -shares, 0, 0, false);
+        
+        Self::transfer_asset(&env, &user, amount);
+        
+        env.events().publish((Symbol::new(&env, "emergency_redeemed"), user), (shares, amount));
+        
+        amount
+    }
+
+    pub fn redeem_lien(env: Env, user: Address, lien_id: u64, shares: i128, to: Address) -> i128 {
+        Self::extend_instance(&env);
+        if shares < MIN_AMOUNT {
+            panic!("Invalid amount");
+        }
+        
+        let exit = Self::get_emergency_exit(env.clone())
+            .unwrap_or_else(|| panic!("No emergency exit"));
+        
+        // Asset-terms counterpart of debit_protected for lien holders
+        Self::release_lien(&env, &user, lien_id, shares, true);
+        
+        let mut bal = Self::get_balance_or_default(env.clone(), user.clone());
+        if bal.protected_shares < shares {
+            panic!("Insufficient protected");
+        }
+        bal.protected_shares = checked_sub(&env, bal.protected_shares, shares);
+        bal.version = checked_add_u64(&env, bal.version, 1);
+        Self::write_persistent(&env, &DataKey::Balance(user.clone()), &bal);
+        
+        Self::update_total_stats(&env, 0, -shares, 0, false);
+        
+        let amount = mul_div(&env, shares, exit.assets_received, exit.shares_withdrawn);
+        Self::transfer_asset(&env, &to, amount);
+        
+        env.events().publish((Symbol::new(&env, "lien_redeemed"), user), (lien_id, to, shares, amount));
         
-        env.storage().instance().set(&DataKey::Paused, &true);
-        env.events().publish((Symbol::new(&env, "paused"),), admin);
+        amount
     }
 
-    pub fn emergency_unpause(env: Env) {
+    pub fn set_breaker_config(env: Env, caller: Address, max_drop_bps: i128, window_secs: u64) {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::ConfigManager);
+        
+        if max_drop_bps <= 0 || max_drop_bps > MAX_BREAKER_DROP_BPS {
+            panic!("Invalid drop");
+        }
+        if window_secs == 0 || window_secs > MAX_BREAKER_WINDOW_SECS {
+            panic!("Invalid window");
+        }
+        
+        env.storage().instance().set(&DataKey::BreakerConfig, &BreakerConfig {
+            max_drop_bps,
+            window_secs,
+        });
+        
+        env.events().publish((Symbol::new(&env, "breaker_config"), caller), (max_drop_bps, window_secs));
+    }
+
+    pub fn check_share_price(env: Env) -> bool {
+        Self::extend_instance(&env);
+        // Permissionless so keepers can trip the breaker between deposits
+        if env.storage().instance().has(&DataKey::EmergencyExit) {
+            return false;
+        }
+        
+        let (total_managed, total_shares) = Self::vault_totals(env.clone());
+        Self::record_share_price(&env, total_managed, total_shares)
+    }
+
+    pub fn reset_breaker(env: Env) {
+        Self::extend_instance(&env);
         let admin: Address = env.storage().instance()
             .get(&DataKey::Admin)
             .unwrap_or_else(|| panic!("Admin not set"));
         admin.require_auth();
         
-        env.storage().instance().set(&DataKey::Paused, &false);
-        env.events().publish((Symbol::new(&env, "unpaused"),), admin);
+        let trip: BreakerTrip = env.storage().instance()
+            .get(&DataKey::BreakerTrip)
+            .unwrap_or_else(|| panic!("Breaker not tripped"));
+        
+        // The next observed price becomes the new reference
+        env.storage().instance().remove(&DataKey::BreakerTrip);
+        env.storage().instance().remove(&DataKey::PriceReference);
+        
+        let mut flags = Self::stored_pause_flags(&env);
+        if trip.paused_deposits {
+            flags.deposits = false;
+        }
+        if trip.paused_locks {
+            flags.locks = false;
+        }
+        env.storage().instance().set(&DataKey::PauseFlags, &flags);
+        
+        env.events().publish((Symbol::new(&env, "breaker_reset"), admin), (trip.reference_price, trip.price));
+    }
+
+    pub fn set_blend_strategy(env: Env, caller: Address, blend_strategy: Address) {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::StrategyManager);
+        Self::validate_non_zero_address(&env, &blend_strategy);
+        
+        // Only affects where future idle funds are invested
+        env.storage().instance().set(&DataKey::BlendStrategy, &blend_strategy);
+        env.events().publish((Symbol::new(&env, "strategy_updated"), caller), blend_strategy);
     }
 
     pub fn deposit(env: Env, user: Address, amount: i128) -> DepositResult {
+        Self::extend_instance(&env);
         user.require_auth();
-        Self::require_not_paused(&env);
+        Self::require_no_emergency_exit(&env);
+        Self::require_current_schema(&env);
+        Self::require_breaker_closed(&env);
+        if Self::get_pause_flags(env.clone()).deposits {
+            panic!("Deposits paused");
+        }
         
         if amount < MIN_AMOUNT {
             panic!("Invalid amount");
@@ -203,6 +764,18 @@ impl BufferContract {
         
         let (total_managed_before, total_shares) = Self::vault_totals(env.clone());
         
+        // A failed call would roll the trip back, so the deposit succeeds
+        // without moving any funds and reports the trip instead
+        if Self::record_share_price(&env, total_managed_before, total_shares) {
+            return DepositResult {
+                shares_minted: 0,
+                amount_deposited: 0,
+                new_available_balance: bal.available_shares,
+                timestamp: current_ts,
+                breaker_tripped: true,
+            };
+        }
+        
         let expected_shares = if total_shares == 0 || total_managed_before == 0 {
             amount
         } else {
@@ -213,11 +786,30 @@ impl BufferContract {
         let min_shares_out = checked_sub(&env, expected_shares, slippage_amount);
 
         let vault_client = DeFindexVaultClient::new(&env, &vault);
+        let asset: Address = env.storage().instance()
+            .get(&DataKey::Asset)
+            .unwrap_or_else(|| panic!("Asset not configured"));
+        let buffer = env.current_contract_address();
+
+        // The buffer holds every vault share and tracks each user's part in
+        // their balance, so the vault pulls the asset from the buffer
+        TokenClient::new(&env, &asset).transfer(&user, &buffer, &amount);
+        env.authorize_as_current_contract(vec![
+            &env,
+            InvokerContractAuthEntry::Contract(SubContractInvocation {
+                context: ContractContext {
+                    contract: asset,
+                    fn_name: Symbol::new(&env, "transfer"),
+                    args: (buffer.clone(), vault.clone(), amount).into_val(&env),
+                },
+                sub_invocations: vec![&env],
+            }),
+        ]);
 
         let result = vault_client.deposit(
             &vec![&env, amount],
             &vec![&env, min_shares_out],
-            &user,
+            &buffer,
             &true
         );
 
@@ -247,9 +839,8 @@ impl BufferContract {
             panic!("Slippage exceeded");
         }
         
-        let mut current_bal: BufferBalance = env.storage().persistent()
-            .get(&DataKey::Balance(user.clone()))
-            .unwrap_or_else(|| BufferBalance {
+        let mut current_bal: BufferBalance = Self::read_persistent(&env, &DataKey::Balance(user.clone()))
+            .unwrap_or(BufferBalance {
                 available_shares: 0,
                 protected_shares: 0,
                 total_deposited: 0,
@@ -266,7 +857,7 @@ impl BufferContract {
         current_bal.last_deposit_ts = current_ts;
         current_bal.version = checked_add_u64(&env, current_bal.version, 1);
         
-        env.storage().persistent().set(&DataKey::Balance(user.clone()), &current_bal);
+        Self::write_persistent(&env, &DataKey::Balance(user.clone()), &current_bal);
         
         Self::update_total_stats(&env, actual_shares, 0, amount, is_new_user);
         
@@ -280,6 +871,7 @@ impl BufferContract {
             amount_deposited: amount,
             new_available_balance: current_bal.available_shares,
             timestamp: current_ts,
+            breaker_tripped: false,
         }
     }
 
@@ -289,18 +881,48 @@ impl BufferContract {
         shares: i128,
         to: Address
     ) -> WithdrawResult {
+        Self::extend_instance(&env);
         user.require_auth();
-        Self::require_not_paused(&env);
+        if Self::get_pause_flags(env.clone()).withdrawals {
+            panic!("Withdrawals paused");
+        }
         Self::withdraw_internal(env, user, shares, to, false)
     }
 
-    pub fn lock_shares(env: Env, user: Address, shares: i128) -> LockResult {
-        Self::require_bridge(env.clone());
-        Self::require_not_paused(&env);
+    pub fn lock_shares(
+        env: Env,
+        consumer: Address,
+        user: Address,
+        shares: i128,
+        expires_at: Option<u64>
+    ) -> LockResult {
+        Self::extend_instance(&env);
+        user.require_auth();
+        let mut entry = Self::require_consumer(&env, &consumer);
+        Self::require_breaker_closed(&env);
+        if Self::get_pause_flags(env.clone()).locks {
+            panic!("Locks paused");
+        }
         
         if shares < MIN_AMOUNT {
             panic!("Invalid amount");
         }
+        
+        entry.locked_shares = checked_add(&env, entry.locked_shares, shares);
+        if entry.locked_shares > entry.debt_ceiling {
+            panic!("Debt ceiling exceeded");
+        }
+        entry.total_locked = checked_add(&env, entry.total_locked, shares);
+        Self::write_persistent(&env, &DataKey::Consumer(consumer.clone()), &entry);
+
+        // Every lien expires, so users can reclaim shares from a stuck consumer
+        let current_ts = env.ledger().timestamp();
+        let max_expiry = current_ts + MAX_LOCK_DURATION_SECS;
+        let expires_at = expires_at.unwrap_or(max_expiry);
+        
+        if expires_at <= current_ts || expires_at > max_expiry {
+            panic!("Invalid expiry");
+        }
 
         let mut bal = Self::get_balance_or_default(env.clone(), user.clone());
         
@@ -312,26 +934,49 @@ impl BufferContract {
         bal.protected_shares = checked_add(&env, bal.protected_shares, shares);
         bal.version = checked_add_u64(&env, bal.version, 1);
 
-        env.storage().persistent().set(&DataKey::Balance(user.clone()), &bal);
+        Self::write_persistent(&env, &DataKey::Balance(user.clone()), &bal);
         
         Self::update_total_stats(&env, -shares, shares, 0, false);
 
-        env.events().publish((Symbol::new(&env, "lock"), user.clone()), shares);
+        let lien_id: u64 = env.storage().instance()
+            .get(&DataKey::LienCounter)
+            .unwrap_or(0u64);
+        let lien_id = checked_add_u64(&env, lien_id, 1);
+        env.storage().instance().set(&DataKey::LienCounter, &lien_id);
+
+        let lien = Lien {
+            id: lien_id,
+            user: user.clone(),
+            owner: consumer.clone(),
+            shares,
+            expires_at,
+            created_at: current_ts,
+        };
+        Self::write_persistent(&env, &DataKey::Lien(lien_id), &lien);
+
+        let mut user_liens: Vec<u64> = Self::read_persistent(&env, &DataKey::UserLiens(user.clone()))
+            .unwrap_or(Vec::new(&env));
+        user_liens.push_back(lien_id);
+        Self::write_persistent(&env, &DataKey::UserLiens(user.clone()), &user_liens);
+
+        env.events().publish((Symbol::new(&env, "lock"), user.clone()), (lien_id, consumer, shares, expires_at));
         
         LockResult {
+            lien_id,
             shares_locked: shares,
             new_available: bal.available_shares,
             new_protected: bal.protected_shares,
         }
     }
 
-    pub fn unlock_shares(env: Env, user: Address, shares: i128) -> LockResult {
-        Self::require_bridge(env.clone());
-        
+    pub fn unlock_shares(env: Env, user: Address, lien_id: u64, shares: i128) -> LockResult {
+        Self::extend_instance(&env);
         if shares < MIN_AMOUNT {
             panic!("Invalid amount");
         }
 
+        Self::release_lien(&env, &user, lien_id, shares, false);
+
         let mut bal = Self::get_balance_or_default(env.clone(), user.clone());
         
         if bal.protected_shares < shares {
@@ -342,50 +987,163 @@ impl BufferContract {
         bal.available_shares = checked_add(&env, bal.available_shares, shares);
         bal.version = checked_add_u64(&env, bal.version, 1);
 
-        env.storage().persistent().set(&DataKey::Balance(user.clone()), &bal);
+        Self::write_persistent(&env, &DataKey::Balance(user.clone()), &bal);
         
         Self::update_total_stats(&env, shares, -shares, 0, false);
 
-        env.events().publish((Symbol::new(&env, "unlock"), user.clone()), shares);
+        env.events().publish((Symbol::new(&env, "unlock"), user.clone()), (lien_id, shares));
         
         LockResult {
+            lien_id,
             shares_locked: shares,
             new_available: bal.available_shares,
             new_protected: bal.protected_shares,
         }
     }
 
-    pub fn debit_available(
-        env: Env,
-        user: Address,
-        shares: i128,
-        to: Address
-    ) -> WithdrawResult {
-        Self::require_bridge(env.clone());
-        Self::withdraw_internal(env, user, shares, to, false)
+    pub fn extend_lien(env: Env, user: Address, lien_id: u64, expires_at: u64) {
+        Self::extend_instance(&env);
+        let mut lien = Self::get_lien(env.clone(), lien_id);
+        
+        if lien.user != user {
+            panic!("Lien not found");
+        }
+        lien.owner.require_auth();
+        
+        let current_ts = env.ledger().timestamp();
+        if expires_at < lien.expires_at || expires_at > current_ts + MAX_LOCK_DURATION_SECS {
+            panic!("Invalid expiry");
+        }
+        
+        lien.expires_at = expires_at;
+        Self::write_persistent(&env, &DataKey::Lien(lien_id), &lien);
+        
+        env.events().publish((Symbol::new(&env, "lien_extended"), user), (lien_id, expires_at));
     }
 
-    pub fn debit_protected(
-        env: Env,
-        user: Address,
-        shares: i128,
-        to: Address
+    pub fn reclaim_expired_lock(env: Env, user: Address, lien_id: u64) -> LockResult {
+        Self::extend_instance(&env);
+        user.require_auth();
+        
+        let lien = Self::get_lien(env.clone(), lien_id);
+        
+        if lien.user != user {
+            panic!("Lien not found");
+        }
+        if env.ledger().timestamp() <= lien.expires_at {
+            panic!("Lien not expired");
+        }
+        
+        let shares = lien.shares;
+        Self::release_consumer_shares(&env, &lien.owner, shares, false);
+        Self::remove_lien(&env, &lien);
+        
+        let mut bal = Self::get_balance_or_default(env.clone(), user.clone());
+        
+        bal.protected_shares = checked_sub(&env, bal.protected_shares, shares);
+        bal.available_shares = checked_add(&env, bal.available_shares, shares);
+        bal.version = checked_add_u64(&env, bal.version, 1);
+        
+        Self::write_persistent(&env, &DataKey::Balance(user.clone()), &bal);
+        
+        Self::update_total_stats(&env, shares, -shares, 0, false);
+        
+        env.events().publish(
+            (Symbol::new(&env, "lock_reclaimed"), user.clone()),
+            (lien_id, lien.owner, shares)
+        );
+        
+        LockResult {
+            lien_id,
+            shares_locked: shares,
+            new_available: bal.available_shares,
+            new_protected: bal.protected_shares,
+        }
+    }
+
+    /// Let a consumer debit up to `shares` of the user's available balance
+    /// without the user signing each debit (e.g. keeper-run collections)
+    pub fn approve_consumer(env: Env, user: Address, consumer: Address, shares: i128) {
+        Self::extend_instance(&env);
+        user.require_auth();
+        
+        if shares < 0 {
+            panic!("Invalid amount");
+        }
+        
+        Self::write_persistent(&env, &DataKey::Allowance(user.clone(), consumer.clone()), &shares);
+        
+        env.events().publish((Symbol::new(&env, "consumer_approved"), user), (consumer, shares));
+    }
+
+    pub fn allowance(env: Env, user: Address, consumer: Address) -> i128 {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::Allowance(user, consumer)).unwrap_or(0)
+    }
+
+    pub fn debit_available(
+        env: Env,
+        consumer: Address,
+        user: Address,
+        shares: i128,
+        to: Address
+    ) -> WithdrawResult {
+        Self::extend_instance(&env);
+        let mut entry = Self::require_consumer(&env, &consumer);
+        if Self::get_pause_flags(env.clone()).debits {
+            panic!("Debits paused");
+        }
+        
+        // Consumers only debit what the user approved
+        let allowance = Self::allowance(env.clone(), user.clone(), consumer.clone());
+        if allowance < shares {
+            panic!("Insufficient allowance");
+        }
+        Self::write_persistent(
+            &env,
+            &DataKey::Allowance(user.clone(), consumer.clone()),
+            &checked_sub(&env, allowance, shares),
+        );
+        
+        entry.total_debited = checked_add(&env, entry.total_debited, shares);
+        Self::write_persistent(&env, &DataKey::Consumer(consumer), &entry);
+        
+        Self::withdraw_internal(env, user, shares, to, false)
+    }
+
+    pub fn debit_protected(
+        env: Env,
+        user: Address,
+        lien_id: u64,
+        shares: i128,
+        to: Address
     ) -> WithdrawResult {
-        Self::require_bridge(env.clone());
+        Self::extend_instance(&env);
+        if shares < MIN_AMOUNT {
+            panic!("Invalid amount");
+        }
+        if Self::get_pause_flags(env.clone()).debits {
+            panic!("Debits paused");
+        }
+        
+        Self::release_lien(&env, &user, lien_id, shares, true);
         Self::withdraw_internal(env, user, shares, to, true)
     }
 
     pub fn get_balance(env: Env, user: Address) -> BufferBalance {
+        Self::extend_instance(&env);
         Self::get_balance_or_default(env, user)
     }
 
     pub fn get_shares(env: Env, user: Address) -> (i128, i128, i128) {
+        Self::extend_instance(&env);
         let bal = Self::get_balance_or_default(env.clone(), user);
         let total = checked_add(&env, bal.available_shares, bal.protected_shares);
         (bal.available_shares, bal.protected_shares, total)
     }
 
     pub fn get_values(env: Env, user: Address) -> (i128, i128, i128) {
+        Self::extend_instance(&env);
         let bal = Self::get_balance_or_default(env.clone(), user);
         let total_shares = checked_add(&env, bal.available_shares, bal.protected_shares);
         
@@ -409,6 +1167,7 @@ impl BufferContract {
     }
 
     pub fn shares_for_amount(env: Env, amount: i128) -> i128 {
+        Self::extend_instance(&env);
         if amount < MIN_AMOUNT {
             panic!("Invalid amount");
         }
@@ -422,8 +1181,98 @@ impl BufferContract {
         }
     }
 
+    pub fn get_consumer(env: Env, consumer: Address) -> Consumer {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::Consumer(consumer))
+            .unwrap_or_else(|| panic!("Consumer not registered"))
+    }
+
+    pub fn get_consumers(env: Env) -> Vec<Address> {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::Consumers).unwrap_or(Vec::new(&env))
+    }
+
+    pub fn get_lien(env: Env, lien_id: u64) -> Lien {
+        Self::find_lien(env, lien_id).unwrap_or_else(|| panic!("Lien not found"))
+    }
+
+    /// Like `get_lien`, but returns None once the lien was released or reclaimed
+    pub fn find_lien(env: Env, lien_id: u64) -> Option<Lien> {
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::Lien(lien_id))
+    }
+
+    pub fn get_liens(env: Env, user: Address) -> Vec<Lien> {
+        Self::extend_instance(&env);
+        let lien_ids: Vec<u64> = Self::read_persistent(&env, &DataKey::UserLiens(user))
+            .unwrap_or(Vec::new(&env));
+        
+        let mut liens = Vec::new(&env);
+        for lien_id in lien_ids.iter() {
+            if let Some(lien) = Self::read_persistent::<Lien>(&env, &DataKey::Lien(lien_id)) {
+                liens.push_back(lien);
+            }
+        }
+        liens
+    }
+
+    pub fn bump(env: Env, user: Address) {
+        Self::extend_instance(&env);
+        // Anyone can pay the rent to keep a dormant buffer from being archived
+        Self::extend_persistent(&env, &DataKey::Balance(user.clone()));
+        Self::extend_persistent(&env, &DataKey::TotalStats);
+        
+        let lien_ids: Vec<u64> = Self::read_persistent(&env, &DataKey::UserLiens(user))
+            .unwrap_or(Vec::new(&env));
+        for lien_id in lien_ids.iter() {
+            Self::extend_persistent(&env, &DataKey::Lien(lien_id));
+        }
+    }
+
+    pub fn upgrade(env: Env, caller: Address, new_wasm_hash: BytesN<32>) {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::Upgrader);
+        
+        // Storage is kept as is; `migrate` must run once the new code is live
+        env.deployer().update_current_contract_wasm(new_wasm_hash.clone());
+        
+        env.events().publish((Symbol::new(&env, "upgraded"), caller), new_wasm_hash);
+    }
+
+    pub fn migrate(env: Env, caller: Address) -> u32 {
+        Self::extend_instance(&env);
+        Self::require_role(&env, &caller, Role::Upgrader);
+        
+        let from = Self::get_schema_version(env.clone());
+        if from >= SCHEMA_VERSION {
+            panic!("Already migrated");
+        }
+        
+        // Every deployment with `upgrade` was stamped by the constructor;
+        // builds from before versioning cannot be upgraded in place.
+        // Later layouts add one conversion step per version here.
+        if from < 2 {
+            Self::migrate_pause_flags(&env);
+        }
+        if from < 3 {
+            Self::require_no_user_held_shares(&env);
+        }
+        
+        env.storage().instance().set(&DataKey::SchemaVersion, &SCHEMA_VERSION);
+        
+        env.events().publish((Symbol::new(&env, "migrated"), caller), (from, SCHEMA_VERSION));
+        
+        SCHEMA_VERSION
+    }
+
+    pub fn get_schema_version(env: Env) -> u32 {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::SchemaVersion).unwrap_or(0)
+    }
+
     pub fn get_total_stats(env: Env) -> TotalStats {
-        env.storage().persistent().get(&DataKey::TotalStats)
+        Self::extend_instance(&env);
+        Self::read_persistent(&env, &DataKey::TotalStats)
             .unwrap_or(TotalStats {
                 total_available: 0,
                 total_protected: 0,
@@ -433,6 +1282,7 @@ impl BufferContract {
     }
 
     pub fn get_config(env: Env) -> ContractConfig {
+        Self::extend_instance(&env);
         env.storage().instance().get(&DataKey::Config)
             .unwrap_or(ContractConfig {
                 min_deposit_interval: DEFAULT_MIN_INTERVAL_SECS,
@@ -440,8 +1290,73 @@ impl BufferContract {
             })
     }
 
+    pub fn get_pending_config(env: Env) -> Option<PendingConfig> {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::PendingConfig)
+    }
+
+    pub fn get_config_delay(env: Env) -> u64 {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::ConfigDelay).unwrap_or(DEFAULT_CONFIG_DELAY_SECS)
+    }
+
+    pub fn get_emergency_exit(env: Env) -> Option<EmergencyExit> {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::EmergencyExit)
+    }
+
+    pub fn get_breaker_config(env: Env) -> BreakerConfig {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::BreakerConfig)
+            .unwrap_or(BreakerConfig {
+                max_drop_bps: DEFAULT_BREAKER_DROP_BPS,
+                window_secs: DEFAULT_BREAKER_WINDOW_SECS,
+            })
+    }
+
+    pub fn get_price_reference(env: Env) -> Option<PriceReference> {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::PriceReference)
+    }
+
+    pub fn get_breaker_trip(env: Env) -> Option<BreakerTrip> {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::BreakerTrip)
+    }
+
+    pub fn get_pause_flags(env: Env) -> PauseFlags {
+        Self::extend_instance(&env);
+        if Self::get_emergency_exit(env.clone()).is_some_and(|exit| exit.holds_pause) {
+            return PauseFlags {
+                deposits: true,
+                withdrawals: true,
+                locks: true,
+                debits: true,
+            };
+        }
+        Self::stored_pause_flags(&env)
+    }
+
     pub fn is_paused(env: Env) -> bool {
-        env.storage().instance().get(&DataKey::Paused).unwrap_or(false)
+        Self::extend_instance(&env);
+        let flags = Self::get_pause_flags(env);
+        flags.deposits || flags.withdrawals || flags.locks || flags.debits
+    }
+
+    pub fn get_admin(env: Env) -> Address {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::Admin)
+            .unwrap_or_else(|| panic!("Admin not set"))
+    }
+
+    pub fn get_pending_admin(env: Env) -> Option<Address> {
+        Self::extend_instance(&env);
+        env.storage().instance().get(&DataKey::PendingAdmin)
+    }
+
+    pub fn has_role(env: Env, role: Role, account: Address) -> bool {
+        Self::extend_instance(&env);
+        Self::extend_persistent(&env, &DataKey::RoleMember(role, account))
     }
 
     fn withdraw_internal(
@@ -454,6 +1369,13 @@ impl BufferContract {
         if shares < MIN_AMOUNT {
             panic!("Invalid amount");
         }
+        Self::require_no_emergency_exit(&env);
+        Self::require_current_schema(&env);
+        
+        // Exits and debits keep working while the breaker is tripped, so
+        // they record a drop before they go ahead
+        let (total_managed, total_shares) = Self::vault_totals(env.clone());
+        Self::record_share_price(&env, total_managed, total_shares);
 
         let vault: Address = env.storage().instance()
             .get(&DataKey::Vault)
@@ -475,10 +1397,12 @@ impl BufferContract {
         
         bal.version = checked_add_u64(&env, bal.version, 1);
 
-        env.storage().persistent().set(&DataKey::Balance(user.clone()), &bal);
+        Self::write_persistent(&env, &DataKey::Balance(user.clone()), &bal);
 
+        // Shares are burned from the buffer, which then pays out the asset
         let vault_client = DeFindexVaultClient::new(&env, &vault);
-        let amounts = vault_client.withdraw(&shares, &vec![&env, 0], &to);
+        let amounts = vault_client.withdraw(&shares, &vec![&env, 0], &env.current_contract_address());
+        Self::transfer_asset(&env, &to, amounts.get(0).unwrap_or(0));
         
         if from_protected {
             Self::update_total_stats(&env, 0, -shares, 0, false);
@@ -500,7 +1424,7 @@ impl BufferContract {
     }
 
     fn get_balance_or_default(env: Env, user: Address) -> BufferBalance {
-        env.storage().persistent().get(&DataKey::Balance(user))
+        Self::read_persistent(&env, &DataKey::Balance(user))
             .unwrap_or(BufferBalance {
                 available_shares: 0,
                 protected_shares: 0,
@@ -512,26 +1436,246 @@ impl BufferContract {
 
     fn validate_non_zero_address(env: &Env, address: &Address) {
         let addr_str = address.to_string();
-        if addr_str.len() == 0 {
+        if addr_str.is_empty() {
             panic!("Zero address");
         }
     }
 
-    fn require_bridge(env: Env) {
-        let bridge: Address = env.storage().instance()
-            .get(&DataKey::Bridge)
-            .unwrap_or_else(|| panic!("Bridge not set"));
-        bridge.require_auth();
+    fn extend_instance(env: &Env) {
+        env.storage().instance().extend_ttl(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+    }
+
+    // Every persistent read and write goes through these so that nothing
+    // in use can be archived. Returns whether the entry exists.
+    fn extend_persistent(env: &Env, key: &DataKey) -> bool {
+        let exists = env.storage().persistent().has(key);
+        if exists {
+            env.storage().persistent().extend_ttl(key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+        }
+        exists
+    }
+
+    fn read_persistent<V: TryFromVal<Env, Val>>(env: &Env, key: &DataKey) -> Option<V> {
+        if Self::extend_persistent(env, key) {
+            env.storage().persistent().get(key)
+        } else {
+            None
+        }
+    }
+
+    fn write_persistent<V: IntoVal<Env, Val>>(env: &Env, key: &DataKey, value: &V) {
+        env.storage().persistent().set(key, value);
+        Self::extend_persistent(env, key);
+    }
+
+    fn require_role(env: &Env, caller: &Address, role: Role) {
+        caller.require_auth();
+        
+        // The admin implicitly holds every role
+        let admin: Address = env.storage().instance()
+            .get(&DataKey::Admin)
+            .unwrap_or_else(|| panic!("Admin not set"));
+        if *caller == admin {
+            return;
+        }
+        
+        if !Self::extend_persistent(env, &DataKey::RoleMember(role, caller.clone())) {
+            panic!("Unauthorized");
+        }
+    }
+
+    fn require_consumer(env: &Env, consumer: &Address) -> Consumer {
+        consumer.require_auth();
+        
+        let entry: Consumer = Self::read_persistent(env, &DataKey::Consumer(consumer.clone()))
+            .unwrap_or_else(|| panic!("Consumer not registered"));
+        
+        if !entry.active {
+            panic!("Consumer revoked");
+        }
+        entry
+    }
+
+    fn release_lien(env: &Env, user: &Address, lien_id: u64, shares: i128, debited: bool) {
+        let mut lien: Lien = Self::read_persistent(env, &DataKey::Lien(lien_id))
+            .unwrap_or_else(|| panic!("Lien not found"));
+        
+        if lien.user != *user {
+            panic!("Lien not found");
+        }
+        
+        // Only the lien holder can release it, even after being revoked
+        lien.owner.require_auth();
+        
+        if lien.shares < shares {
+            panic!("Insufficient lien");
+        }
+        
+        lien.shares = checked_sub(env, lien.shares, shares);
+        
+        Self::release_consumer_shares(env, &lien.owner, shares, debited);
+        
+        if lien.shares == 0 {
+            Self::remove_lien(env, &lien);
+        } else {
+            Self::write_persistent(env, &DataKey::Lien(lien_id), &lien);
+        }
+    }
+
+    fn remove_lien(env: &Env, lien: &Lien) {
+        env.storage().persistent().remove(&DataKey::Lien(lien.id));
+        
+        let mut user_liens: Vec<u64> = Self::read_persistent(env, &DataKey::UserLiens(lien.user.clone()))
+            .unwrap_or(Vec::new(env));
+        if let Some(index) = user_liens.first_index_of(lien.id) {
+            user_liens.remove(index);
+        }
+        Self::write_persistent(env, &DataKey::UserLiens(lien.user.clone()), &user_liens);
+    }
+
+    fn release_consumer_shares(env: &Env, consumer: &Address, shares: i128, debited: bool) {
+        let consumer_key = DataKey::Consumer(consumer.clone());
+        if let Some(mut entry) = Self::read_persistent::<Consumer>(env, &consumer_key) {
+            entry.locked_shares = checked_sub(env, entry.locked_shares, shares);
+            if debited {
+                entry.total_debited = checked_add(env, entry.total_debited, shares);
+            }
+            Self::write_persistent(env, &consumer_key, &entry);
+        }
     }
 
-    fn require_not_paused(env: &Env) {
+    fn migrate_pause_flags(env: &Env) {
+        // The old switch blocked deposits, withdrawals and locks but never debits
         let paused: bool = env.storage().instance().get(&DataKey::Paused).unwrap_or(false);
-        if paused {
-            panic!("Contract paused");
+        env.storage().instance().set(&DataKey::PauseFlags, &PauseFlags {
+            deposits: paused,
+            withdrawals: paused,
+            locks: paused,
+            debits: false,
+        });
+        env.storage().instance().remove(&DataKey::Paused);
+    }
+
+    // Schema 3 keeps every vault share in the buffer. Older builds minted
+    // them to each user, and moving them would take every user's signature,
+    // so a buffer with shares on record has to be redeployed instead.
+    fn require_no_user_held_shares(env: &Env) {
+        let stats = Self::get_total_stats(env.clone());
+        if stats.total_available != 0 || stats.total_protected != 0 {
+            panic!("Redeploy required");
+        }
+    }
+
+    fn require_current_schema(env: &Env) {
+        // Vault calls assume buffer custody, which `migrate` checks for
+        if Self::get_schema_version(env.clone()) < SCHEMA_VERSION {
+            panic!("Migration required");
+        }
+    }
+
+    // Flags set by a Pauser (or the breaker), without the exit's hold
+    fn stored_pause_flags(env: &Env) -> PauseFlags {
+        env.storage().instance().get(&DataKey::PauseFlags)
+            .unwrap_or(PauseFlags {
+                deposits: false,
+                withdrawals: false,
+                locks: false,
+                debits: false,
+            })
+    }
+
+    fn require_no_emergency_exit(env: &Env) {
+        // The vault position is gone, only emergency redemptions remain
+        if env.storage().instance().has(&DataKey::EmergencyExit) {
+            panic!("Emergency exit active");
+        }
+    }
+
+    fn require_breaker_closed(env: &Env) {
+        // Unlike the pause flags, only the admin can clear this (reset_breaker)
+        if env.storage().instance().has(&DataKey::BreakerTrip) {
+            panic!("Breaker tripped");
+        }
+    }
+
+    // Returns true when the breaker is (or just got) tripped
+    fn record_share_price(env: &Env, total_managed: i128, total_shares: i128) -> bool {
+        if env.storage().instance().has(&DataKey::BreakerTrip) {
+            return true;
+        }
+        
+        let mut trip = match Self::observe_share_price(env, total_managed, total_shares) {
+            Some(trip) => trip,
+            None => return false,
+        };
+        
+        let mut flags = Self::stored_pause_flags(env);
+        trip.paused_deposits = !flags.deposits;
+        trip.paused_locks = !flags.locks;
+        flags.deposits = true;
+        flags.locks = true;
+        env.storage().instance().set(&DataKey::PauseFlags, &flags);
+        env.storage().instance().set(&DataKey::BreakerTrip, &trip);
+        
+        env.events().publish(
+            (Symbol::new(env, "breaker_tripped"),),
+            (trip.reference_price, trip.price, trip.drop_bps)
+        );
+        
+        true
+    }
+
+    // Updates the reference price and returns the trip a drop past the
+    // threshold causes; record_share_price stores it
+    fn observe_share_price(env: &Env, total_managed: i128, total_shares: i128) -> Option<BreakerTrip> {
+        if total_managed <= 0 || total_shares <= 0 {
+            return None;
         }
+        
+        let config = Self::get_breaker_config(env.clone());
+        let price = mul_div(env, total_managed, PRICE_SCALE, total_shares);
+        let now = env.ledger().timestamp();
+        
+        let reference = match Self::get_price_reference(env.clone()) {
+            // Within the window the highest price seen stays the reference
+            Some(r) if now.saturating_sub(r.recorded_at) < config.window_secs && price < r.price => r,
+            _ => {
+                env.storage().instance().set(&DataKey::PriceReference, &PriceReference {
+                    price,
+                    recorded_at: now,
+                });
+                return None;
+            }
+        };
+        
+        let drop_bps = mul_div(env, reference.price - price, BPS_DIVISOR, reference.price);
+        if drop_bps <= config.max_drop_bps {
+            return None;
+        }
+        
+        Some(BreakerTrip {
+            reference_price: reference.price,
+            price,
+            drop_bps,
+            tripped_at: now,
+            paused_deposits: false,
+            paused_locks: false,
+        })
+    }
+
+    fn transfer_asset(env: &Env, to: &Address, amount: i128) {
+        let asset: Address = env.storage().instance()
+            .get(&DataKey::Asset)
+            .unwrap_or_else(|| panic!("Asset not configured"));
+        TokenClient::new(env, &asset).transfer(&env.current_contract_address(), to, &amount);
     }
 
     fn vault_totals(env: Env) -> (i128, i128) {
+        // After an emergency exit values are quoted at the frozen rate
+        if let Some(exit) = Self::get_emergency_exit(env.clone()) {
+            return (exit.assets_received, exit.shares_withdrawn);
+        }
+        
         let vault: Address = env.storage().instance()
             .get(&DataKey::Vault)
             .unwrap_or_else(|| panic!("Vault not configured"));
@@ -555,8 +1699,7 @@ impl BufferContract {
         deposited_delta: i128,
         is_new_user: bool,
     ) {
-        let mut stats: TotalStats = env.storage().persistent()
-            .get(&DataKey::TotalStats)
+        let mut stats: TotalStats = Self::read_persistent(env, &DataKey::TotalStats)
             .unwrap_or(TotalStats {
                 total_available: 0,
                 total_protected: 0,
@@ -573,7 +1716,7 @@ impl BufferContract {
                 .unwrap_or_else(|| panic!("Math overflow"));
         }
         
-        env.storage().persistent().set(&DataKey::TotalStats, &stats);
+        Self::write_persistent(env, &DataKey::TotalStats, &stats);
     }
 }
 
@@ -617,4 +1760,931 @@ fn mul_div_ceil(env: &Env, a: i128, b: i128, c: i128) -> i128 {
         div.checked_add(1)
             .unwrap_or_else(|| panic!("Math overflow"))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soroban_sdk::testutils::{storage::Persistent as _, Address as _, Ledger, MockAuth, MockAuthInvoke};
+    use soroban_sdk::token::StellarAssetClient;
+    use soroban_sdk::{symbol_short, Bytes};
+
+    // Vault minting one share per asset deposited and returning 3 assets
+    // for every 2 shares it burns
+    #[contract]
+    pub struct MockVault;
+
+    #[contractimpl]
+    impl MockVault {
+        pub fn __constructor(env: Env, asset: Address) {
+            env.storage().instance().set(&DataKey::Asset, &asset);
+        }
+
+        pub fn deposit(
+            env: Env,
+            amounts_desired: Vec<i128>,
+            _amounts_min: Vec<i128>,
+            from: Address,
+            _invest: bool,
+        ) -> (Vec<i128>, i128, Option<Vec<i128>>) {
+            from.require_auth();
+            let amount = amounts_desired.get(0).unwrap();
+            let asset: Address = env.storage().instance().get(&DataKey::Asset).unwrap();
+            TokenClient::new(&env, &asset).transfer(&from, &env.current_contract_address(), &amount);
+
+            let held = Self::balance(env.clone(), from.clone());
+            Self::set_balance(env.clone(), from, held + amount);
+            let supply = Self::total_supply(env.clone());
+            env.storage().instance().set(&symbol_short!("supply"), &(supply + amount));
+            (amounts_desired, amount, None)
+        }
+
+        pub fn total_supply(env: Env) -> i128 {
+            env.storage().instance().get(&symbol_short!("supply")).unwrap_or(0)
+        }
+
+        pub fn fetch_total_managed_funds(env: Env) -> Vec<vault_import::CurrentAssetInvestmentAllocation> {
+            let asset: Address = env.storage().instance().get(&DataKey::Asset).unwrap();
+            let total = TokenClient::new(&env, &asset).balance(&env.current_contract_address());
+            vec![&env, vault_import::CurrentAssetInvestmentAllocation {
+                asset,
+                idle_amount: 0,
+                invested_amount: total,
+                strategy_allocations: vec![&env],
+                total_amount: total,
+            }]
+        }
+
+        pub fn set_balance(env: Env, id: Address, shares: i128) {
+            env.storage().instance().set(&DataKey::Balance(id), &shares);
+        }
+
+        pub fn balance(env: Env, id: Address) -> i128 {
+            env.storage().instance().get(&DataKey::Balance(id)).unwrap_or(0)
+        }
+
+        pub fn withdraw(env: Env, withdraw_shares: i128, _min_amounts_out: Vec<i128>, from: Address) -> Vec<i128> {
+            let held = Self::balance(env.clone(), from.clone());
+            Self::set_balance(env.clone(), from.clone(), held - withdraw_shares);
+
+            let amount = withdraw_shares * 3 / 2;
+            let asset: Address = env.storage().instance().get(&DataKey::Asset).unwrap();
+            TokenClient::new(&env, &asset).transfer(&env.current_contract_address(), &from, &amount);
+            vec![&env, amount]
+        }
+    }
+
+    struct TestContext {
+        env: Env,
+        admin: Address,
+        user: Address,
+        buffer: Address,
+    }
+
+    impl TestContext {
+        fn new() -> Self {
+            let env = Env::default();
+            env.mock_all_auths();
+            let vault = Address::generate(&env);
+            let asset = Address::generate(&env);
+            Self::with_vault(env, vault, asset)
+        }
+
+        fn with_vault(env: Env, vault: Address, asset: Address) -> Self {
+            let admin = Address::generate(&env);
+            let buffer = env.register(BufferContract, (
+                admin.clone(),
+                vault,
+                asset,
+                Address::generate(&env),
+            ));
+
+            Self {
+                env: env.clone(),
+                admin,
+                user: Address::generate(&env),
+                buffer,
+            }
+        }
+
+        fn client(&self) -> BufferContractClient<'_> {
+            BufferContractClient::new(&self.env, &self.buffer)
+        }
+
+        fn seed_balance(&self, available_shares: i128) {
+            self.seed_balance_of(&self.user, available_shares);
+        }
+
+        fn seed_balance_of(&self, user: &Address, available_shares: i128) {
+            self.env.as_contract(&self.buffer, || {
+                self.env.storage().persistent().set(&DataKey::Balance(user.clone()), &BufferBalance {
+                    available_shares,
+                    protected_shares: 0,
+                    total_deposited: available_shares,
+                    last_deposit_ts: 0,
+                    version: 1,
+                });
+            });
+        }
+    }
+
+    // Next buffer release used by the upgrade test: schema 4 moves the
+    // consumer list from instance to persistent storage. It is registered
+    // natively over the upgraded contract, which keeps the contract's
+    // storage like an on-chain Wasm update does.
+    #[contract]
+    pub struct BufferV2;
+
+    #[contractimpl]
+    impl BufferV2 {
+        pub fn migrate(env: Env) -> u32 {
+            let from = BufferContract::get_schema_version(env.clone());
+            assert!(from < 4, "Already migrated");
+
+            let consumers = BufferContract::get_consumers(env.clone());
+            env.storage().instance().remove(&DataKey::Consumers);
+            env.storage().persistent().set(&DataKey::Consumers, &consumers);
+
+            env.storage().instance().set(&DataKey::SchemaVersion, &4u32);
+            4
+        }
+
+        pub fn get_consumers(env: Env) -> Vec<Address> {
+            env.storage().persistent().get(&DataKey::Consumers).unwrap_or(Vec::new(&env))
+        }
+
+        pub fn get_balance(env: Env, user: Address) -> BufferBalance {
+            BufferContract::get_balance(env, user)
+        }
+
+        pub fn get_liens(env: Env, user: Address) -> Vec<Lien> {
+            BufferContract::get_liens(env, user)
+        }
+    }
+
+    #[test]
+    fn test_upgrade_keeps_balances_and_liens() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        let lock = client.lock_shares(&consumer, &ctx.user, &400, &None);
+
+        let wasm_hash = ctx.env.deployer().upload_contract_wasm(Bytes::new(&ctx.env));
+        client.upgrade(&ctx.admin, &wasm_hash);
+        ctx.env.register_at(&ctx.buffer, BufferV2, ());
+
+        let v2 = BufferV2Client::new(&ctx.env, &ctx.buffer);
+        assert_eq!(v2.migrate(), 4);
+        assert_eq!(v2.get_consumers(), vec![&ctx.env, consumer.clone()]);
+
+        let bal = v2.get_balance(&ctx.user);
+        assert_eq!(bal.available_shares, 600);
+        assert_eq!(bal.protected_shares, 400);
+
+        let liens = v2.get_liens(&ctx.user);
+        assert_eq!(liens.len(), 1);
+        assert_eq!(liens.get(0).unwrap().id, lock.lien_id);
+        assert_eq!(liens.get(0).unwrap().shares, 400);
+        assert_eq!(liens.get(0).unwrap().owner, consumer);
+    }
+
+    #[test]
+    fn test_migrate_converts_legacy_pause() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        // Schema 1 storage, paused through the old single switch
+        ctx.env.as_contract(&ctx.buffer, || {
+            let storage = ctx.env.storage().instance();
+            storage.remove(&DataKey::PauseFlags);
+            storage.set(&DataKey::Paused, &true);
+            storage.set(&DataKey::SchemaVersion, &1u32);
+        });
+
+        client.migrate(&ctx.admin);
+
+        assert_eq!(client.get_pause_flags(), PauseFlags {
+            deposits: true,
+            withdrawals: true,
+            locks: true,
+            debits: false,
+        });
+        ctx.env.as_contract(&ctx.buffer, || {
+            assert!(!ctx.env.storage().instance().has(&DataKey::Paused));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Redeploy required")]
+    fn test_migrate_refuses_user_held_vault_shares() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        // Schema 2 deposits minted the vault shares to each user
+        ctx.env.as_contract(&ctx.buffer, || {
+            ctx.env.storage().persistent().set(&DataKey::TotalStats, &TotalStats {
+                total_available: 1000,
+                total_protected: 0,
+                total_deposited: 1000,
+                unique_users: 1,
+            });
+            ctx.env.storage().instance().set(&DataKey::SchemaVersion, &2u32);
+        });
+
+        client.migrate(&ctx.admin);
+    }
+
+    #[test]
+    #[should_panic(expected = "Migration required")]
+    fn test_unmigrated_buffer_refuses_vault_calls() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        ctx.seed_balance(1000);
+        ctx.env.as_contract(&ctx.buffer, || {
+            ctx.env.storage().instance().set(&DataKey::SchemaVersion, &2u32);
+        });
+
+        client.withdraw_available(&ctx.user, &100, &ctx.user);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn test_upgrade_requires_upgrader_role() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let stranger = Address::generate(&ctx.env);
+        client.upgrade(&stranger, &BytesN::from_array(&ctx.env, &[0u8; 32]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Already migrated")]
+    fn test_migrate_current_schema_fails() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let upgrader = Address::generate(&ctx.env);
+        client.grant_role(&Role::Upgrader, &upgrader);
+        client.migrate(&upgrader);
+    }
+
+    #[test]
+    fn test_bump_keeps_balance_and_liens_alive() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        let lock = client.lock_shares(&consumer, &ctx.user, &400, &None);
+
+        let ttl = |key: DataKey| ctx.env.as_contract(&ctx.buffer, || {
+            ctx.env.storage().persistent().get_ttl(&key)
+        });
+
+        // Twice the original TTL goes by, bumping before each window runs out
+        for _ in 0..4 {
+            ctx.env.ledger().with_mut(|li| li.sequence_number += PERSISTENT_BUMP_AMOUNT / 2);
+            client.bump(&ctx.user);
+            assert_eq!(ttl(DataKey::Balance(ctx.user.clone())), PERSISTENT_BUMP_AMOUNT);
+            assert_eq!(ttl(DataKey::Lien(lock.lien_id)), PERSISTENT_BUMP_AMOUNT);
+            assert_eq!(ttl(DataKey::UserLiens(ctx.user.clone())), PERSISTENT_BUMP_AMOUNT);
+        }
+
+        let bal = client.get_balance(&ctx.user);
+        assert_eq!(bal.available_shares, 600);
+        assert_eq!(bal.protected_shares, 400);
+        assert_eq!(client.get_lien(&lock.lien_id).shares, 400);
+    }
+
+    #[test]
+    fn test_balance_reads_extend_ttl() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        // Written without a bump, so it starts at the network minimum TTL
+        ctx.seed_balance(1000);
+
+        ctx.env.ledger().with_mut(|li| li.sequence_number += 1000);
+        assert_eq!(client.get_balance(&ctx.user).available_shares, 1000);
+
+        let ttl = ctx.env.as_contract(&ctx.buffer, || {
+            ctx.env.storage().persistent().get_ttl(&DataKey::Balance(ctx.user.clone()))
+        });
+        assert_eq!(ttl, PERSISTENT_BUMP_AMOUNT);
+    }
+
+    #[test]
+    fn test_config_change_waits_for_timelock() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        ctx.env.ledger().set_timestamp(1000);
+
+        let executable_at = client.propose_config(&ctx.admin, &60, &100, &(3 * 86400));
+        assert_eq!(executable_at, 1000 + DEFAULT_CONFIG_DELAY_SECS);
+        assert_eq!(client.get_config().slippage_tolerance_bps, DEFAULT_SLIPPAGE_BPS);
+
+        ctx.env.ledger().set_timestamp(executable_at);
+        let config = client.execute_config(&ctx.admin);
+        assert_eq!(config.min_deposit_interval, 60);
+        assert_eq!(client.get_config().slippage_tolerance_bps, 100);
+        assert_eq!(client.get_config_delay(), 3 * 86400);
+        assert!(client.get_pending_config().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Timelock active")]
+    fn test_config_execute_before_delay_fails() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        ctx.env.ledger().set_timestamp(1000);
+
+        client.propose_config(&ctx.admin, &60, &100, &DEFAULT_CONFIG_DELAY_SECS);
+        ctx.env.ledger().set_timestamp(1000 + DEFAULT_CONFIG_DELAY_SECS - 1);
+        client.execute_config(&ctx.admin);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid slippage")]
+    fn test_config_rejects_out_of_range_slippage() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        client.propose_config(&ctx.admin, &60, &(BPS_DIVISOR + 1), &DEFAULT_CONFIG_DELAY_SECS);
+    }
+
+    #[test]
+    fn test_paused_inflows_still_let_users_exit() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        let lock = client.lock_shares(&consumer, &ctx.user, &400, &None);
+
+        let pauser = Address::generate(&ctx.env);
+        client.grant_role(&Role::Pauser, &pauser);
+        client.set_pause_flags(&pauser, &PauseFlags {
+            deposits: true,
+            withdrawals: false,
+            locks: true,
+            debits: false,
+        });
+        assert!(client.is_paused());
+
+        assert!(client.try_deposit(&ctx.user, &100).is_err());
+        assert!(client.try_lock_shares(&consumer, &ctx.user, &100, &None).is_err());
+
+        // Collateral can still be released
+        client.unlock_shares(&ctx.user, &lock.lien_id, &400);
+        assert_eq!(client.get_balance(&ctx.user).available_shares, 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Debits paused")]
+    fn test_paused_debits_block_bridge() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        let lock = client.lock_shares(&consumer, &ctx.user, &400, &None);
+
+        client.set_pause_flags(&ctx.admin, &PauseFlags {
+            deposits: false,
+            withdrawals: false,
+            locks: false,
+            debits: true,
+        });
+        client.debit_protected(&ctx.user, &lock.lien_id, &100, &Address::generate(&ctx.env));
+    }
+
+    #[test]
+    fn test_lock_requires_user_auth() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        client.lock_shares(&consumer, &ctx.user, &400, &None);
+
+        assert!(ctx.env.auths().iter().any(|(address, _)| *address == ctx.user));
+    }
+
+    #[test]
+    fn test_debit_available_spends_allowance() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let asset = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        let vault = env.register(MockVault, (asset.clone(),));
+        let ctx = TestContext::with_vault(env.clone(), vault.clone(), asset.clone());
+        let client = ctx.client();
+
+        StellarAssetClient::new(&env, &asset).mint(&vault, &3000);
+        MockVaultClient::new(&env, &vault).set_balance(&ctx.buffer, &2000);
+
+        let consumer = Address::generate(&env);
+        let merchant = Address::generate(&env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        client.approve_consumer(&ctx.user, &consumer, &300);
+
+        client.debit_available(&consumer, &ctx.user, &200, &merchant);
+        assert_eq!(client.allowance(&ctx.user, &consumer), 100);
+        assert_eq!(client.get_balance(&ctx.user).available_shares, 800);
+        assert_eq!(TokenClient::new(&env, &asset).balance(&merchant), 300);
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient allowance")]
+    fn test_debit_available_requires_allowance() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        // A registered consumer cannot take shares the user never approved
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        client.approve_consumer(&ctx.user, &consumer, &100);
+        client.debit_available(&consumer, &ctx.user, &200, &consumer);
+    }
+
+    #[test]
+    fn test_only_nominee_accepts_admin() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let nominee = Address::generate(&ctx.env);
+        let stranger = Address::generate(&ctx.env);
+        client.propose_admin(&nominee);
+        assert_eq!(client.get_pending_admin(), Some(nominee.clone()));
+
+        ctx.env.mock_auths(&[MockAuth {
+            address: &stranger,
+            invoke: &MockAuthInvoke {
+                contract: &ctx.buffer,
+                fn_name: "accept_admin",
+                args: ().into_val(&ctx.env),
+                sub_invokes: &[],
+            },
+        }]);
+        assert!(client.try_accept_admin().is_err());
+        assert_eq!(client.get_admin(), ctx.admin);
+
+        ctx.env.mock_all_auths();
+        client.accept_admin();
+        assert_eq!(client.get_admin(), nominee);
+        assert_eq!(client.get_pending_admin(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn test_old_admin_loses_rights_after_transfer() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let nominee = Address::generate(&ctx.env);
+        client.propose_admin(&nominee);
+        client.accept_admin();
+
+        // Admin-only calls now need the new admin's signature
+        let pauser = Address::generate(&ctx.env);
+        client.grant_role(&Role::Pauser, &pauser);
+        assert_eq!(ctx.env.auths()[0].0, nominee);
+
+        client.emergency_pause(&ctx.admin);
+    }
+
+    #[test]
+    #[should_panic(expected = "No pending admin")]
+    fn test_cancel_admin_proposal_clears_nominee() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        client.propose_admin(&Address::generate(&ctx.env));
+        client.cancel_admin_proposal();
+        assert_eq!(client.get_pending_admin(), None);
+
+        client.accept_admin();
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn test_revoked_pauser_cannot_pause() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let pauser = Address::generate(&ctx.env);
+        client.grant_role(&Role::Pauser, &pauser);
+        client.emergency_pause(&pauser);
+        client.emergency_unpause(&pauser);
+
+        client.revoke_role(&Role::Pauser, &pauser);
+        assert!(!client.has_role(&Role::Pauser, &pauser));
+        client.emergency_pause(&pauser);
+    }
+
+    #[test]
+    #[should_panic(expected = "Role not granted")]
+    fn test_renounced_role_is_gone() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let manager = Address::generate(&ctx.env);
+        client.grant_role(&Role::ConfigManager, &manager);
+        client.renounce_role(&Role::ConfigManager, &manager);
+        assert!(!client.has_role(&Role::ConfigManager, &manager));
+
+        // Nothing left to renounce or revoke
+        client.revoke_role(&Role::ConfigManager, &manager);
+    }
+
+    #[test]
+    fn test_get_liens_lists_each_lock() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        ctx.env.ledger().set_timestamp(1000);
+
+        let bridge = Address::generate(&ctx.env);
+        let lender = Address::generate(&ctx.env);
+        client.add_consumer(&bridge, &1000);
+        client.add_consumer(&lender, &1000);
+        ctx.seed_balance(1000);
+
+        let first = client.lock_shares(&bridge, &ctx.user, &300, &Some(5000));
+        let second = client.lock_shares(&lender, &ctx.user, &200, &None);
+
+        let liens = client.get_liens(&ctx.user);
+        assert_eq!(liens.len(), 2);
+        let lien = liens.get(0).unwrap();
+        assert_eq!((lien.id, lien.owner, lien.shares, lien.expires_at), (first.lien_id, bridge.clone(), 300, 5000));
+        let lien = liens.get(1).unwrap();
+        assert_eq!((lien.id, lien.owner, lien.shares), (second.lien_id, lender, 200));
+        assert_eq!(lien.expires_at, 1000 + MAX_LOCK_DURATION_SECS);
+
+        // Partly released liens shrink, fully released ones drop out
+        client.unlock_shares(&ctx.user, &first.lien_id, &100);
+        client.unlock_shares(&ctx.user, &second.lien_id, &200);
+        let liens = client.get_liens(&ctx.user);
+        assert_eq!(liens.len(), 1);
+        assert_eq!(liens.get(0).unwrap().shares, 200);
+        assert_eq!(client.get_balance(&ctx.user).protected_shares, 200);
+    }
+
+    #[test]
+    #[should_panic(expected = "Debt ceiling exceeded")]
+    fn test_consumer_ceiling_caps_new_locks() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        client.lock_shares(&consumer, &ctx.user, &600, &None);
+
+        // Lowering the ceiling keeps the existing lien but caps the next lock
+        client.set_consumer_ceiling(&consumer, &800);
+        let entry = client.get_consumer(&consumer);
+        assert_eq!((entry.debt_ceiling, entry.locked_shares), (800, 600));
+        client.lock_shares(&consumer, &ctx.user, &300, &None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Consumer revoked")]
+    fn test_revoked_consumer_cannot_lock() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        let lock = client.lock_shares(&consumer, &ctx.user, &400, &None);
+
+        // Existing liens can still be released after revocation
+        client.revoke_consumer(&consumer);
+        client.unlock_shares(&ctx.user, &lock.lien_id, &100);
+        assert_eq!(client.get_lien(&lock.lien_id).shares, 300);
+
+        client.lock_shares(&consumer, &ctx.user, &100, &None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Consumer revoked")]
+    fn test_revoked_consumer_cannot_debit() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        client.approve_consumer(&ctx.user, &consumer, &500);
+
+        client.revoke_consumer(&consumer);
+        client.debit_available(&consumer, &ctx.user, &100, &consumer);
+    }
+
+    #[test]
+    fn test_reclaim_expired_lock_frees_shares() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        ctx.env.ledger().set_timestamp(1000);
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        let lock = client.lock_shares(&consumer, &ctx.user, &400, &Some(5000));
+
+        ctx.env.ledger().set_timestamp(5001);
+        let result = client.reclaim_expired_lock(&ctx.user, &lock.lien_id);
+        assert_eq!((result.shares_locked, result.new_available, result.new_protected), (400, 1000, 0));
+
+        // The lien is gone and no longer counts against the consumer
+        assert!(client.find_lien(&lock.lien_id).is_none());
+        assert!(client.get_liens(&ctx.user).is_empty());
+        assert_eq!(client.get_consumer(&consumer).locked_shares, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Lien not expired")]
+    fn test_reclaim_before_expiry_fails() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        ctx.env.ledger().set_timestamp(1000);
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        let lock = client.lock_shares(&consumer, &ctx.user, &400, &Some(5000));
+
+        ctx.env.ledger().set_timestamp(5000);
+        client.reclaim_expired_lock(&ctx.user, &lock.lien_id);
+    }
+
+    #[test]
+    fn test_emergency_exit_redeems_pro_rata() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let asset = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        let vault = env.register(MockVault, (asset.clone(),));
+        let ctx = TestContext::with_vault(env.clone(), vault.clone(), asset.clone());
+        let client = ctx.client();
+        let token = TokenClient::new(&env, &asset);
+
+        // 2000 shares in the vault, worth 3000 assets
+        StellarAssetClient::new(&env, &asset).mint(&vault, &3000);
+        MockVaultClient::new(&env, &vault).set_balance(&ctx.buffer, &2000);
+
+        let other = Address::generate(&env);
+        let consumer = Address::generate(&env);
+        let merchant = Address::generate(&env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+        ctx.seed_balance_of(&other, 1000);
+        let lock = client.lock_shares(&consumer, &ctx.user, &400, &None);
+
+        let exit = client.emergency_withdraw();
+        assert_eq!(exit.shares_withdrawn, 2000);
+        assert_eq!(exit.assets_received, 3000);
+        assert_eq!(token.balance(&ctx.buffer), 3000);
+        assert!(client.get_pause_flags().debits);
+
+        // Available shares only; the lien stays with its holder
+        assert_eq!(client.redeem_emergency(&ctx.user), 900);
+        assert_eq!(client.get_balance(&ctx.user).protected_shares, 400);
+
+        assert_eq!(client.redeem_lien(&ctx.user, &lock.lien_id, &400, &merchant), 600);
+        assert_eq!(client.redeem_emergency(&other), 1500);
+
+        assert_eq!(token.balance(&ctx.user), 900);
+        assert_eq!(token.balance(&merchant), 600);
+        assert_eq!(token.balance(&other), 1500);
+        assert_eq!(token.balance(&ctx.buffer), 0);
+    }
+
+    #[test]
+    fn test_emergency_exit_redeems_deposited_shares() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let asset = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        let vault = env.register(MockVault, (asset.clone(),));
+        let ctx = TestContext::with_vault(env.clone(), vault.clone(), asset.clone());
+        let client = ctx.client();
+        let vault_client = MockVaultClient::new(&env, &vault);
+
+        StellarAssetClient::new(&env, &asset).mint(&ctx.user, &1000);
+        let deposit = client.deposit(&ctx.user, &1000);
+        assert_eq!(deposit.shares_minted, 1000);
+
+        // The vault shares sit with the buffer, not the depositor
+        assert_eq!(vault_client.balance(&ctx.buffer), 1000);
+        assert_eq!(vault_client.balance(&ctx.user), 0);
+
+        // Yield brings the 1000 shares to 1500 assets
+        StellarAssetClient::new(&env, &asset).mint(&vault, &500);
+        let exit = client.emergency_withdraw();
+        assert_eq!(exit.shares_withdrawn, 1000);
+        assert_eq!(exit.assets_received, 1500);
+        assert_eq!(vault_client.balance(&ctx.buffer), 0);
+
+        assert_eq!(client.redeem_emergency(&ctx.user), 1500);
+        assert_eq!(TokenClient::new(&env, &asset).balance(&ctx.user), 1500);
+    }
+
+    #[test]
+    #[should_panic(expected = "Emergency exit active")]
+    fn test_emergency_exit_blocks_vault_withdrawals() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let asset = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        let vault = env.register(MockVault, (asset.clone(),));
+        let ctx = TestContext::with_vault(env.clone(), vault.clone(), asset.clone());
+        let client = ctx.client();
+
+        StellarAssetClient::new(&env, &asset).mint(&vault, &1500);
+        MockVaultClient::new(&env, &vault).set_balance(&ctx.buffer, &1000);
+        ctx.seed_balance(1000);
+
+        client.emergency_withdraw();
+        client.emergency_unpause(&ctx.admin);
+        client.withdraw_available(&ctx.user, &100, &ctx.user);
+    }
+
+    #[test]
+    fn test_only_admin_lifts_exit_pause() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let asset = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        let vault = env.register(MockVault, (asset.clone(),));
+        let ctx = TestContext::with_vault(env.clone(), vault.clone(), asset.clone());
+        let client = ctx.client();
+
+        StellarAssetClient::new(&env, &asset).mint(&vault, &1500);
+        MockVaultClient::new(&env, &vault).set_balance(&ctx.buffer, &1000);
+        let all_paused = PauseFlags { deposits: true, withdrawals: true, locks: true, debits: true };
+
+        client.emergency_withdraw();
+        assert_eq!(client.get_pause_flags(), all_paused);
+
+        // A Pauser only clears its own flags, the exit's stay set
+        let pauser = Address::generate(&env);
+        client.grant_role(&Role::Pauser, &pauser);
+        client.emergency_unpause(&pauser);
+        client.set_pause_flags(&pauser, &PauseFlags { deposits: false, withdrawals: false, locks: false, debits: false });
+        assert_eq!(client.get_pause_flags(), all_paused);
+
+        client.emergency_unpause(&ctx.admin);
+        assert!(!client.is_paused());
+        assert!(!client.get_emergency_exit().unwrap().holds_pause);
+    }
+
+    #[test]
+    fn test_breaker_trips_on_price_drop() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        ctx.env.ledger().set_timestamp(1000);
+
+        let consumer = Address::generate(&ctx.env);
+        client.add_consumer(&consumer, &1000);
+        ctx.seed_balance(1000);
+
+        ctx.env.as_contract(&ctx.buffer, || {
+            // 1.5 per share, then a 4% dip stays under the default 5%
+            assert!(!BufferContract::record_share_price(&ctx.env, 3000, 2000));
+            assert!(!BufferContract::record_share_price(&ctx.env, 2880, 2000));
+        });
+        assert_eq!(client.get_price_reference().unwrap().price, 15_000_000);
+
+        ctx.env.ledger().set_timestamp(2000);
+        ctx.env.as_contract(&ctx.buffer, || {
+            assert!(BufferContract::record_share_price(&ctx.env, 2700, 2000));
+        });
+
+        let trip = client.get_breaker_trip().unwrap();
+        assert_eq!(trip.drop_bps, 1000);
+        let flags = client.get_pause_flags();
+        assert!(flags.deposits && flags.locks);
+        assert!(!flags.withdrawals && !flags.debits);
+
+        // A pauser cannot resume new locks on their own
+        client.emergency_unpause(&ctx.admin);
+        assert!(client.try_lock_shares(&consumer, &ctx.user, &100, &None).is_err());
+
+        client.reset_breaker();
+        assert!(client.get_breaker_trip().is_none());
+        assert!(client.get_price_reference().is_none());
+        client.lock_shares(&consumer, &ctx.user, &100, &None);
+    }
+
+    #[test]
+    fn test_breaker_reset_keeps_manual_pause() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        ctx.env.ledger().set_timestamp(1000);
+
+        let pauser = Address::generate(&ctx.env);
+        client.grant_role(&Role::Pauser, &pauser);
+        client.set_pause_flags(&pauser, &PauseFlags {
+            deposits: true,
+            withdrawals: false,
+            locks: false,
+            debits: false,
+        });
+
+        ctx.env.as_contract(&ctx.buffer, || {
+            assert!(!BufferContract::record_share_price(&ctx.env, 3000, 2000));
+            assert!(BufferContract::record_share_price(&ctx.env, 2700, 2000));
+        });
+        let trip = client.get_breaker_trip().unwrap();
+        assert!(!trip.paused_deposits && trip.paused_locks);
+
+        // Only the locks the breaker paused come back
+        client.reset_breaker();
+        let flags = client.get_pause_flags();
+        assert!(flags.deposits);
+        assert!(!flags.locks);
+    }
+
+    #[test]
+    fn test_deposit_trips_breaker_on_price_drop() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let asset = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        let vault = env.register(MockVault, (asset.clone(),));
+        let ctx = TestContext::with_vault(env.clone(), vault.clone(), asset.clone());
+        let client = ctx.client();
+        let token = TokenClient::new(&env, &asset);
+
+        StellarAssetClient::new(&env, &asset).mint(&ctx.user, &2000);
+        client.deposit(&ctx.user, &1000);
+        client.check_share_price();
+
+        // The vault loses 10% of its assets
+        token.burn(&vault, &100);
+        env.ledger().set_timestamp(env.ledger().timestamp() + 10);
+
+        // The deposit records the trip and leaves the funds with the user
+        let result = client.deposit(&ctx.user, &1000);
+        assert!(result.breaker_tripped);
+        assert_eq!((result.shares_minted, result.new_available_balance), (0, 1000));
+        assert_eq!(token.balance(&ctx.user), 1000);
+        assert_eq!(client.get_breaker_trip().unwrap().drop_bps, 1000);
+        assert!(client.get_pause_flags().deposits);
+    }
+
+    #[test]
+    fn test_withdrawal_trips_breaker_on_price_drop() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let asset = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        let vault = env.register(MockVault, (asset.clone(),));
+        let ctx = TestContext::with_vault(env.clone(), vault.clone(), asset.clone());
+        let client = ctx.client();
+
+        StellarAssetClient::new(&env, &asset).mint(&ctx.user, &1000);
+        client.deposit(&ctx.user, &1000);
+        client.check_share_price();
+        TokenClient::new(&env, &asset).burn(&vault, &100);
+
+        // Exits still go through, with the breaker tripped behind them
+        client.withdraw_available(&ctx.user, &100, &ctx.user);
+        assert_eq!(client.get_balance(&ctx.user).available_shares, 900);
+        assert!(client.get_breaker_trip().is_some());
+        assert!(client.get_pause_flags().locks);
+    }
+
+    #[test]
+    fn test_breaker_ignores_drop_after_window() {
+        let ctx = TestContext::new();
+        let client = ctx.client();
+        ctx.env.ledger().set_timestamp(1000);
+
+        client.set_breaker_config(&ctx.admin, &500, &3600);
+
+        ctx.env.as_contract(&ctx.buffer, || {
+            assert!(!BufferContract::record_share_price(&ctx.env, 3000, 2000));
+        });
+
+        // Same 10% fall, but spread beyond the window
+        ctx.env.ledger().set_timestamp(1000 + 3600);
+        ctx.env.as_contract(&ctx.buffer, || {
+            assert!(!BufferContract::record_share_price(&ctx.env, 2700, 2000));
+        });
+
+        assert!(client.get_breaker_trip().is_none());
+        assert_eq!(client.get_price_reference().unwrap().price, 13_500_000);
+    }
+}